@@ -3,9 +3,8 @@ use futures::StreamExt;
 use geoutils::Location;
 use ntrip_client::{
     config::{NtripConfig, NtripCredentials},
-    NtripClient,
+    CancellationToken, NtripClient,
 };
-use tokio::select;
 use tracing::{debug, error, info, level_filters::LevelFilter};
 use tracing_subscriber::{fmt::Subscriber as FmtSubscriber, EnvFilter};
 
@@ -64,15 +63,17 @@ async fn main() -> Result<(), anyhow::Error> {
     debug!("Args {args:?}");
 
     // Setup interrupt / exit handler
-    let (exit_tx, mut exit_rx) = tokio::sync::broadcast::channel(1);
-    let e = exit_tx.clone();
+    let exit = CancellationToken::new();
+    let e = exit.clone();
     tokio::task::spawn(async move {
         tokio::signal::ctrl_c().await.unwrap();
         debug!("Received Ctrl-C, shutting down...");
-        e.send(()).unwrap();
+        e.cancel();
     });
 
-    let mut client = NtripClient::new(args.ntrip_host.clone(), args.ntrip_creds.clone()).await?;
+    let mut client = NtripClient::new(args.ntrip_host.clone(), args.ntrip_creds.clone())
+        .await?
+        .with_cancellation_token(exit.clone());
 
     match args.command {
         Commands::List => {
@@ -120,25 +121,17 @@ async fn main() -> Result<(), anyhow::Error> {
             debug!("Connecting to NTRIP server");
 
             // Setup the NTRIP client
-            let mut client = client.mount(mount, exit_tx.clone()).await?;
-
-            // Process incoming RTCM messages
-            loop {
-                select! {
-                    m = client.next() => match m {
-                        Some(m) => {
-                            info!("Received RTCM message: {:?}", m);
-                        },
-                        None => {
-                            error!("NTRIP client stream ended");
-                            break;
-                        }
-                    },
-                    _ = exit_rx.recv() => {
-                        info!("Exiting on signal");
-                        break;
-                    }
-                }
+            let mut client = client.mount(mount).await?;
+
+            // Process incoming RTCM messages, until the stream ends or Ctrl-C
+            while let Some(m) = client.next().await {
+                info!("Received RTCM message: {:?}", m);
+            }
+
+            if exit.is_cancelled() {
+                info!("Exiting on signal");
+            } else {
+                error!("NTRIP client stream ended");
             }
         },
     }
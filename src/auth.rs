@@ -0,0 +1,397 @@
+//! HTTP authentication schemes
+//!
+//! NTRIP casters mostly rely on Basic authentication, but some advertise
+//! Digest (`D` in the sourcetable STR authentication field) and newer
+//! correction services issue Bearer tokens. The scheme is selected with
+//! [AuthScheme]: in [AuthScheme::Auto] mode, credentials are sent preemptively
+//! (Bearer token, or Basic) and the `WWW-Authenticate` challenges of a
+//! `401` response select the scheme of a single retry.
+
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose, Engine as _};
+use http::HeaderValue;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use strum::{Display, EnumString, VariantNames};
+use tracing::debug;
+
+use crate::{config::NtripCredentials, NtripClientError};
+
+/// Authentication scheme selection
+#[derive(Clone, Copy, Default, PartialEq, Debug, EnumString, Display, VariantNames)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AuthScheme {
+    /// Send Bearer or Basic credentials, follow the server challenges
+    #[default]
+    #[strum(serialize = "auto")]
+    Auto,
+    /// Never authenticate
+    #[strum(serialize = "none")]
+    None,
+    /// Basic authentication only
+    #[strum(serialize = "basic")]
+    Basic,
+    /// Digest authentication only (MD5 or SHA-256, `qop=auth`)
+    #[strum(serialize = "digest")]
+    Digest,
+    /// Bearer token only
+    #[strum(serialize = "bearer")]
+    Bearer,
+}
+
+/// Authentication challenge of a `WWW-Authenticate` header
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct AuthChallenge {
+    /// Scheme name, e.g. "Digest"
+    pub scheme: String,
+    /// Parameters, with lower case names
+    pub params: HashMap<String, String>,
+}
+
+impl AuthChallenge {
+    /// Parses all challenges of a `WWW-Authenticate` header value
+    ///
+    /// ```
+    /// use ntrip_client::auth::AuthChallenge;
+    ///
+    /// let challenges = AuthChallenge::parse_all(
+    ///     r#"Digest realm="caster", nonce="abc", qop="auth", Basic realm="caster""#
+    /// );
+    ///
+    /// assert_eq!(challenges.len(), 2);
+    /// assert_eq!(challenges[0].scheme, "Digest");
+    /// assert_eq!(challenges[0].param("nonce"), Some("abc"));
+    /// assert_eq!(challenges[1].scheme, "Basic");
+    /// ```
+    pub fn parse_all(value: &str) -> Vec<Self> {
+        let mut challenges: Vec<Self> = Vec::new();
+        let mut rest = value.trim();
+
+        while !rest.is_empty() {
+            rest = rest.trim_start_matches([',', ' ', '\t']);
+
+            // Token: either a parameter name or a new scheme
+            let end = rest
+                .find(|c: char| c == '=' || c == ',' || c.is_whitespace())
+                .unwrap_or(rest.len());
+            let token = &rest[..end];
+            rest = rest[end..].trim_start();
+
+            if token.is_empty() {
+                break;
+            }
+
+            match (rest.strip_prefix('='), challenges.last_mut()) {
+                (Some(value), Some(challenge)) => {
+                    let (value, remaining) = parse_param_value(value.trim_start());
+                    challenge.params.insert(token.to_lowercase(), value);
+                    rest = remaining;
+                },
+                (Some(value), None) => {
+                    // Parameter without a scheme: ignore it
+                    rest = parse_param_value(value.trim_start()).1;
+                },
+                (None, _) => challenges.push(Self {
+                    scheme: token.to_string(),
+                    params: HashMap::new(),
+                }),
+            }
+        }
+
+        challenges
+    }
+
+    /// Returns a parameter value
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(|v| v.as_str())
+    }
+
+    fn is(&self, scheme: &str) -> bool {
+        self.scheme.eq_ignore_ascii_case(scheme)
+    }
+}
+
+/// Parses a token or quoted string, returns it with the remaining input
+fn parse_param_value(s: &str) -> (String, &str) {
+    if let Some(quoted) = s.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = quoted.char_indices();
+
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => {
+                    if let Some((_, c)) = chars.next() {
+                        value.push(c);
+                    }
+                },
+                '"' => return (value, &quoted[i + 1..]),
+                c => value.push(c),
+            }
+        }
+
+        (value, "")
+    } else {
+        let end = s.find(',').unwrap_or(s.len());
+        (s[..end].trim().to_string(), &s[end..])
+    }
+}
+
+/// Digest hash algorithms
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum DigestAlgorithm {
+    Md5,
+    Sha256,
+}
+
+impl DigestAlgorithm {
+    fn hash(&self, data: &str) -> String {
+        let bytes = match self {
+            Self::Md5 => Md5::digest(data.as_bytes()).to_vec(),
+            Self::Sha256 => Sha256::digest(data.as_bytes()).to_vec(),
+        };
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+/// Computes the `Authorization` value answering a Digest challenge.
+/// Returns None for unsupported challenges (algorithm, `qop=auth-int` only).
+pub(crate) fn digest_authorization(
+    challenge: &AuthChallenge,
+    creds: &NtripCredentials,
+    method: &str,
+    uri: &str,
+    cnonce: &str,
+) -> Option<String> {
+    let realm = challenge.param("realm").unwrap_or_default();
+    let nonce = challenge.param("nonce")?;
+
+    let algorithm_name = challenge.param("algorithm").unwrap_or("MD5");
+    let (algorithm, session) = match algorithm_name.to_uppercase().as_str() {
+        "MD5" => (DigestAlgorithm::Md5, false),
+        "MD5-SESS" => (DigestAlgorithm::Md5, true),
+        "SHA-256" => (DigestAlgorithm::Sha256, false),
+        "SHA-256-SESS" => (DigestAlgorithm::Sha256, true),
+        _ => return None,
+    };
+
+    let qop = match challenge.param("qop") {
+        Some(qop) if qop.split(',').any(|q| q.trim() == "auth") => Some("auth"),
+        Some(_) => return None,
+        None => None,
+    };
+
+    let nc = "00000001";
+
+    let mut ha1 = algorithm.hash(&format!("{}:{}:{}", creds.user, realm, creds.pass));
+    if session {
+        ha1 = algorithm.hash(&format!("{}:{}:{}", ha1, nonce, cnonce));
+    }
+    let ha2 = algorithm.hash(&format!("{}:{}", method, uri));
+
+    let response = match qop {
+        Some(qop) => algorithm.hash(&format!(
+            "{}:{}:{}:{}:{}:{}",
+            ha1, nonce, nc, cnonce, qop, ha2
+        )),
+        None => algorithm.hash(&format!("{}:{}:{}", ha1, nonce, ha2)),
+    };
+
+    let mut value = format!(
+        "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", algorithm={}, response=\"{}\"",
+        creds.user, realm, nonce, uri, algorithm_name, response
+    );
+
+    if let Some(qop) = qop {
+        value.push_str(&format!(", qop={}, nc={}, cnonce=\"{}\"", qop, nc, cnonce));
+    }
+
+    if let Some(opaque) = challenge.param("opaque") {
+        value.push_str(&format!(", opaque=\"{}\"", opaque));
+    }
+
+    Some(value)
+}
+
+fn basic_authorization(creds: &NtripCredentials) -> String {
+    let auth = general_purpose::STANDARD.encode(format!("{}:{}", creds.user, creds.pass));
+    format!("Basic {}", auth)
+}
+
+fn bearer_authorization(token: &str) -> String {
+    format!("Bearer {}", token)
+}
+
+/// Client nonce for Digest authentication
+fn cnonce() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    let hash = Sha256::digest(format!("{}:{}:{}", now, count, std::process::id()).as_bytes());
+    general_purpose::STANDARD_NO_PAD.encode(&hash[..16])
+}
+
+/// `Authorization` header value, marked sensitive so it is redacted from [Debug] output
+fn header_value(value: String) -> Result<HeaderValue, NtripClientError> {
+    let mut value = HeaderValue::from_str(&value)?;
+    value.set_sensitive(true);
+    Ok(value)
+}
+
+/// `Authorization` sent with the first request
+pub(crate) fn preemptive_authorization(
+    scheme: AuthScheme,
+    creds: &NtripCredentials,
+) -> Result<Option<HeaderValue>, NtripClientError> {
+    let value = match scheme {
+        AuthScheme::Auto => match &creds.token {
+            Some(token) => Some(bearer_authorization(token)),
+            None if !creds.user.is_empty() => Some(basic_authorization(creds)),
+            None => None,
+        },
+        AuthScheme::Basic if !creds.user.is_empty() => Some(basic_authorization(creds)),
+        AuthScheme::Bearer => creds.token.as_deref().map(bearer_authorization),
+        _ => None,
+    };
+
+    value.map(header_value).transpose()
+}
+
+/// `Authorization` answering the challenges of a `401` response,
+/// or None if no supported challenge can be answered
+pub(crate) fn challenge_authorization(
+    scheme: AuthScheme,
+    creds: &NtripCredentials,
+    challenges: &[AuthChallenge],
+    method: &str,
+    uri: &str,
+) -> Result<Option<HeaderValue>, NtripClientError> {
+    let has_user = !creds.user.is_empty();
+
+    let digest = || {
+        challenges
+            .iter()
+            .filter(|c| c.is("Digest"))
+            .find_map(|c| digest_authorization(c, creds, method, uri, &cnonce()))
+    };
+    let basic = || {
+        challenges
+            .iter()
+            .any(|c| c.is("Basic"))
+            .then(|| basic_authorization(creds))
+    };
+    let bearer = || {
+        challenges
+            .iter()
+            .any(|c| c.is("Bearer"))
+            .then_some(creds.token.as_deref())
+            .flatten()
+            .map(bearer_authorization)
+    };
+
+    let value = match scheme {
+        AuthScheme::Auto if has_user => digest().or_else(bearer).or_else(basic),
+        AuthScheme::Auto => bearer(),
+        AuthScheme::Digest if has_user => digest(),
+        AuthScheme::Basic if has_user => basic(),
+        AuthScheme::Bearer => bearer(),
+        _ => None,
+    };
+
+    if let Some(value) = &value {
+        debug!(
+            "Answering {} challenge",
+            value.split_whitespace().next().unwrap_or_default()
+        );
+    }
+
+    value.map(header_value).transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mufasa(pass: &str) -> NtripCredentials {
+        NtripCredentials::default()
+            .with_username("Mufasa")
+            .with_password(pass)
+    }
+
+    #[test]
+    fn test_digest_rfc2617() {
+        let challenge = &AuthChallenge::parse_all(
+            r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#,
+        )[0];
+
+        let value = digest_authorization(
+            challenge,
+            &mufasa("Circle Of Life"),
+            "GET",
+            "/dir/index.html",
+            "0a4f113b",
+        )
+        .unwrap();
+
+        assert!(value.contains(r#"response="6629fae49393a05397450978507c4ef1""#));
+        assert!(value.contains("qop=auth, nc=00000001"));
+        assert!(value.contains(r#"opaque="5ccc069c403ebaf9f0171e9517f40e41""#));
+    }
+
+    #[test]
+    fn test_digest_rfc7616() {
+        let creds = mufasa("Circle of Life");
+        let cnonce = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+
+        for (algorithm, expected) in [
+            ("MD5", "8ca523f5e9506fed4657c9700eebdbec"),
+            (
+                "SHA-256",
+                "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1",
+            ),
+        ] {
+            let challenge = &AuthChallenge::parse_all(&format!(
+                r#"Digest realm="http-auth@example.org", qop="auth, auth-int", algorithm={}, nonce="7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v", opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#,
+                algorithm
+            ))[0];
+
+            let value =
+                digest_authorization(challenge, &creds, "GET", "/dir/index.html", cnonce).unwrap();
+            assert!(value.contains(&format!("response=\"{}\"", expected)));
+        }
+    }
+
+    #[test]
+    fn test_scheme_selection() {
+        let creds = mufasa("pass");
+        let challenges =
+            AuthChallenge::parse_all(r#"Basic realm="x", Digest realm="x", nonce="n""#);
+
+        let auth = |scheme| {
+            challenge_authorization(scheme, &creds, &challenges, "GET", "/MOUNT")
+                .unwrap()
+                .map(|v| v.to_str().unwrap().to_string())
+        };
+
+        assert!(auth(AuthScheme::Auto).unwrap().starts_with("Digest "));
+        assert!(auth(AuthScheme::Basic).unwrap().starts_with("Basic "));
+        assert_eq!(auth(AuthScheme::Bearer), None);
+        assert_eq!(auth(AuthScheme::None), None);
+
+        let token = creds.with_token("abc");
+        let value = preemptive_authorization(AuthScheme::Auto, &token)
+            .unwrap()
+            .unwrap();
+        assert_eq!(value.to_str().unwrap(), "Bearer abc");
+        assert!(value.is_sensitive());
+    }
+}
@@ -0,0 +1,170 @@
+//! Blocking NTRIP client
+//!
+//! [NtripClient] mirrors the async [crate::NtripClient] for synchronous
+//! programs: it manages its own tokio runtime and mounts return an
+//! [Iterator] of messages.
+//!
+//! Do not use it from within an async context: blocking on the internal
+//! runtime there panics.
+//!
+//! ```no_run
+//! use ntrip_client::{blocking::NtripClient, NtripConfig, NtripCredentials, RtcmProvider};
+//!
+//! let config = NtripConfig::from_provider(RtcmProvider::Centipede);
+//! let creds = NtripCredentials::default()
+//!     .with_username("centipede")
+//!     .with_password("password");
+//!
+//! let mut client = NtripClient::new(config, creds).unwrap();
+//!
+//! for mount in client.list_mounts().unwrap().services {
+//!     println!("{} - {}", mount.name, mount.details);
+//! }
+//!
+//! for message in client.mount("VALDM").unwrap().take(10) {
+//!     println!("received RTCM message: {:?}", message);
+//! }
+//! ```
+
+use std::sync::Arc;
+
+use futures::StreamExt;
+use rtcm_rs::Message;
+use tokio::runtime::{Builder, Runtime};
+
+use crate::{
+    config::{MountOptions, NtripConfig, NtripCredentials},
+    credentials::CredentialProvider,
+    snip::ServerInfo,
+    CloseSummary, NtripClientError, NtripHandle,
+};
+
+/// Blocking NTRIP client, see the [module](crate::blocking) documentation
+pub struct NtripClient {
+    inner: crate::NtripClient,
+    runtime: Arc<Runtime>,
+}
+
+impl NtripClient {
+    pub fn new(config: NtripConfig, creds: NtripCredentials) -> Result<Self, NtripClientError> {
+        Self::with_credential_provider(config, creds)
+    }
+
+    /// Builds an [NtripClient] querying this [CredentialProvider] before each connection
+    pub fn with_credential_provider(
+        config: NtripConfig,
+        provider: impl CredentialProvider + 'static,
+    ) -> Result<Self, NtripClientError> {
+        // A worker thread keeps mounted streams flowing between two reads
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("ntrip-client")
+            .enable_all()
+            .build()?;
+
+        let inner = runtime.block_on(crate::NtripClient::with_credential_provider(
+            config, provider,
+        ))?;
+
+        Ok(Self {
+            inner,
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    /// List available mounts on the NTRIP server
+    pub fn list_mounts(&mut self) -> Result<ServerInfo, NtripClientError> {
+        self.runtime.block_on(self.inner.list_mounts())
+    }
+
+    /// 'Mount' the [NtripClient] from remote $url/$mount service point.
+    ///
+    /// ## Output
+    /// - [NtripIter] which implements [Iterator] to receive messages
+    pub fn mount(&mut self, mount: impl ToString) -> Result<NtripIter, NtripClientError> {
+        self.mount_with_options(mount, MountOptions::default())
+    }
+
+    /// 'Mount' the [NtripClient] like [Self::mount], with these [MountOptions]
+    pub fn mount_with_options(
+        &mut self,
+        mount: impl ToString,
+        options: MountOptions,
+    ) -> Result<NtripIter, NtripClientError> {
+        let handle = self
+            .runtime
+            .block_on(self.inner.mount_with_options(mount, options))?;
+
+        Ok(NtripIter {
+            handle,
+            runtime: self.runtime.clone(),
+        })
+    }
+}
+
+/// Blocking [Iterator] of the messages of a mount,
+/// ending with the stream. Dropping it closes the connection.
+pub struct NtripIter {
+    handle: NtripHandle,
+    runtime: Arc<Runtime>,
+}
+
+impl NtripIter {
+    /// Returns the underlying [NtripHandle], for statistics and recording
+    pub fn handle(&self) -> &NtripHandle {
+        &self.handle
+    }
+
+    /// Closes the connection, see [NtripHandle::close]
+    pub fn close(self) -> CloseSummary {
+        self.runtime.block_on(self.handle.close())
+    }
+
+    /// Closes the connection gracefully: the messages still buffered
+    /// remain available from this [Iterator], see [NtripHandle::close_gracefully]
+    pub fn close_gracefully(&mut self) -> CloseSummary {
+        self.runtime.block_on(self.handle.close_gracefully())
+    }
+}
+
+impl Iterator for NtripIter {
+    type Item = Message;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.runtime.block_on(self.handle.next())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{station_frame, MockCaster, MockMount};
+
+    #[test]
+    fn test_blocking_client() {
+        // The caster runs on its own runtime
+        let caster_runtime = Runtime::new().unwrap();
+        let data = [station_frame(1), station_frame(2)].concat();
+        let caster = caster_runtime
+            .block_on(
+                MockCaster::default()
+                    .with_mount("VALDM", MockMount::icy().with_data(&data).then_close())
+                    .start(),
+            )
+            .unwrap();
+
+        let mut client = NtripClient::new(caster.config(), NtripCredentials::default()).unwrap();
+
+        let stations = client
+            .mount("VALDM")
+            .unwrap()
+            .map(|m| match m {
+                Message::Msg1005(m) => m.reference_station_id,
+                m => panic!("unexpected message {:?}", m),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(stations, vec![1, 2]);
+
+        assert!(client.mount("NONE").is_err());
+    }
+}
@@ -1,31 +1,41 @@
 //! NTRIP Client implementation
 
-use std::sync::Arc;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime},
+};
 
-use base64::{engine::general_purpose, Engine as _};
 use futures::Stream;
 use http::{
-    header::{InvalidHeaderValue, ToStrError, USER_AGENT},
-    HeaderMap, HeaderValue, Method,
+    header::{AUTHORIZATION, WWW_AUTHENTICATE},
+    HeaderValue, Method, StatusCode,
 };
 use rtcm_rs::{Message, MessageFrame};
-use rustls::pki_types::{InvalidDnsNameError, ServerName};
 use tokio::{
     io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt},
-    net::TcpStream,
     select,
-    sync::{
-        broadcast::Sender as BroadcastSender,
-        mpsc::{unbounded_channel, UnboundedReceiver},
-    },
+    sync::mpsc::{unbounded_channel, UnboundedReceiver},
     task::JoinHandle,
 };
-use tokio_rustls::TlsConnector;
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, trace, warn};
 
 use crate::{
-    config::{NtripConfig, NtripCredentials},
+    auth::{challenge_authorization, preemptive_authorization, AuthChallenge},
+    config::{MountOptions, NtripConfig, NtripCredentials, Transport},
+    connector::{Connector, NtripStream, TcpConnector},
+    credentials::CredentialProvider,
+    envelope::Envelope,
+    framer::{message_number, FramerStats, RtcmFramer},
+    latency::LatencyStats,
+    protocol::{encode_mount, mount_request, user_agent, BodyDecoder, ResponseHead},
+    proxy::format_host,
+    recorder::{RecordMode, Recorder, RecorderWriter},
+    rtsp,
     snip::ServerInfo,
+    station::{is_station_message, ReferenceStation},
+    stats::StreamStats,
+    NtripClientError,
 };
 
 /// NTRIP Client, used to connect to an NTRIP (RTCM) service.
@@ -33,8 +43,6 @@ use crate::{
 /// through a [Stream] channel.
 ///
 /// ```
-/// use tokio::select;
-/// use tokio::sync; // broadcast channem
 /// use futures::StreamExt; // real-time channel
 ///
 /// use ntrip_client::{
@@ -67,67 +75,84 @@ use crate::{
 ///         println!("{} - {}", remote.name, remote.details);
 ///     }
 ///
-///     // this channel allows graceful exit
-///     let (exit_tx, mut exit_rx) = sync::broadcast::channel(1);
-///
 ///     // subscribe to remote server
-///     let mut handle = client.mount("VALDM", exit_tx).await?;
+///     let mut handle = client.mount("VALDM").await?;
 ///
 ///     // listening
-///     loop {
-///         select! {
-///             message = handle.next() => match message {
-///                 Some(msg) => {
-///                     println!("received RTCM message: {:?}", msg);
-///                 },
-///                 None => {
-///                     println!("End of stream!");
-///                     break;
-///                 },
-///             },
-///             _ = exit_rx.recv() => {
-///                 println!("graceful exit");
+///     for _ in 0..10 {
+///         match handle.next().await {
+///             Some(msg) => println!("received RTCM message: {:?}", msg),
+///             None => {
+///                 println!("End of stream!");
 ///                 break;
 ///             },
 ///         }
 ///     }
 ///
+///     // disconnect (dropping the handle does too)
+///     handle.close().await;
+///
 ///     Ok(())
 /// }
 ///
 /// basic_listener();
 /// ```
+#[derive(Clone)]
 pub struct NtripClient {
     config: NtripConfig,
-    creds: NtripCredentials,
+    credentials: Arc<dyn CredentialProvider>,
+    connector: Option<Arc<dyn Connector>>,
+    cancel: Option<CancellationToken>,
 }
 
 /// [NtripHandle] is the Mount handle, it implements [Stream]
 /// which is how you can receiver messages in real-time.
+/// Dropping it closes the connection.
 pub struct NtripHandle {
-    _rx_handle: tokio::task::JoinHandle<()>,
-    ntrip_rx: UnboundedReceiver<Message>,
+    rx_handle: Option<JoinHandle<()>>,
+    ntrip_rx: UnboundedReceiver<Envelope>,
+    shared: SharedState,
+    cancel: CancellationToken,
+    drain: CancellationToken,
 }
 
-#[derive(Debug, thiserror::Error)]
-pub enum NtripClientError {
-    #[error("Io error: {0}")]
-    Io(#[from] std::io::Error),
-
-    #[error("Reqwest error: {0}")]
-    Reqwest(#[from] reqwest::Error),
-
-    #[error("Invalid header value {0}")]
-    InvalidHeaderValue(#[from] InvalidHeaderValue),
+/// Final counters of a closed stream, see [NtripHandle::close_gracefully]
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CloseSummary {
+    /// Body bytes received from the server, after HTTP chunked transfer decoding
+    pub bytes_received: u64,
+    /// Messages delivered to the handle
+    pub frames_delivered: u64,
+    /// Bytes dropped: garbage, corrupted frames and trailing partial frame
+    pub bytes_discarded: u64,
+}
 
-    #[error("Invalid DNS name {0}")]
-    InvalidDnsName(#[from] InvalidDnsNameError),
+/// [Stream] of [Envelope]s, see [NtripHandle::into_envelopes]
+pub struct NtripEnvelopes(NtripHandle);
+
+/// State shared by an [NtripHandle] and its reader task
+#[derive(Clone, Default)]
+pub(crate) struct SharedState {
+    pub recorder: Arc<Mutex<Option<RecorderWriter>>>,
+    pub framer_stats: Arc<Mutex<FramerStats>>,
+    pub stream_stats: Arc<Mutex<StreamStats>>,
+    pub station: Arc<Mutex<Option<ReferenceStation>>>,
+    pub latency: Arc<Mutex<LatencyStats>>,
+    pub summary: Arc<Mutex<CloseSummary>>,
+}
 
-    #[error("Header ToStrError error {0}")]
-    ToStrError(#[from] ToStrError),
+impl SharedState {
+    /// Updates the [ReferenceStation] from this station message
+    pub fn update_station(&self, message: &Message) {
+        let mut station = self.station.lock().unwrap();
+        let mut updated = station.clone().unwrap_or_default();
 
-    #[error("Response error")]
-    ResponseError(String),
+        if updated.update(message) {
+            trace!("Reference station update: {:?}", updated);
+            *station = Some(updated);
+        }
+    }
 }
 
 impl NtripClient {
@@ -135,36 +160,121 @@ impl NtripClient {
         config: NtripConfig,
         creds: NtripCredentials,
     ) -> Result<Self, NtripClientError> {
-        Ok(NtripClient { config, creds })
+        Self::with_credential_provider(config, creds).await
+    }
+
+    /// Builds an [NtripClient] querying this [CredentialProvider] before each connection
+    pub async fn with_credential_provider(
+        config: NtripConfig,
+        provider: impl CredentialProvider + 'static,
+    ) -> Result<Self, NtripClientError> {
+        Ok(NtripClient {
+            config,
+            credentials: Arc::new(provider),
+            connector: None,
+            cancel: None,
+        })
+    }
+
+    /// Copies and returns [NtripClient] opening its connections through this
+    /// [Connector] instead of the default [TcpConnector].
+    /// The sourcetable is then requested through it too.
+    pub fn with_connector(&self, connector: impl Connector + 'static) -> Self {
+        let mut s = self.clone();
+        s.connector = Some(Arc::new(connector));
+        s
+    }
+
+    /// Copies and returns [NtripClient] whose mounts are closed when this
+    /// [CancellationToken] is cancelled, to shut down several mounts at once
+    pub fn with_cancellation_token(&self, token: CancellationToken) -> Self {
+        let mut s = self.clone();
+        s.cancel = Some(token);
+        s
     }
 
     /// List available mounts on the NTRIP server
     pub async fn list_mounts(&mut self) -> Result<ServerInfo, NtripClientError> {
-        let client = reqwest::Client::builder()
+        // A TLS server name is presented over the same dial (and proxy tunnel) as mounts
+        let server_name = self.config.use_tls && self.config.tls.server_name.is_some();
+
+        if self.connector.is_some() || server_name {
+            return self.fetch_sourcetable().await;
+        }
+
+        let mut builder = reqwest::Client::builder()
             .http1_ignore_invalid_headers_in_responses(true)
             .http09_responses()
-            .user_agent(format!(
-                "NTRIP {}/{}",
-                env!("CARGO_PKG_NAME"),
-                env!("CARGO_PKG_VERSION")
-            ))
-            .build()?;
-
-        // TODO: auth etc.
+            .user_agent(user_agent())
+            .local_address(self.config.local_address)
+            .no_proxy();
+
+        if let Some(interface) = &self.config.interface {
+            builder = bind_interface(builder, interface)?;
+        }
+
+        if let Some(proxy) = self.config.effective_proxy() {
+            debug!("Using proxy {}:{}", proxy.host, proxy.port);
+            builder = builder.proxy(proxy.to_reqwest()?);
+        }
+
+        if self.config.use_tls {
+            builder = builder.use_preconfigured_tls(self.config.tls.client_config()?);
+        }
+
+        let client = builder.build()?;
+
         let proto = if self.config.use_tls { "https" } else { "http" };
+        let url = format!(
+            "{}://{}:{}",
+            proto,
+            format_host(&self.config.host),
+            self.config.port
+        );
 
-        let req = client
-            .request(
-                Method::GET,
-                format!("{}://{}:{}", proto, self.config.host, self.config.port),
-            )
-            .header("Ntrip-Version", "NTRIP/2.0")
-            .build()?;
+        let request = |authorization: Option<&HeaderValue>| {
+            let mut req = client
+                .request(Method::GET, &url)
+                .header("Ntrip-Version", "Ntrip/2.0");
 
-        let res = client.execute(req).await?;
+            if let Some(authorization) = authorization {
+                req = req.header(AUTHORIZATION, authorization.clone());
+            }
+
+            req.build()
+        };
+
+        let creds = self.credentials.credentials(&self.config).await?;
+        let authorization = preemptive_authorization(self.config.auth, &creds)?;
+        let mut res = client.execute(request(authorization.as_ref())?).await?;
+
+        if res.status() == StatusCode::UNAUTHORIZED {
+            let challenges = res
+                .headers()
+                .get_all(WWW_AUTHENTICATE)
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .flat_map(AuthChallenge::parse_all)
+                .collect::<Vec<_>>();
+
+            let retry = challenge_authorization(self.config.auth, &creds, &challenges, "GET", "/")?;
+
+            if let Some(retry) = retry.filter(|r| authorization.as_ref() != Some(r)) {
+                debug!("Retrying with challenge response");
+                res = client.execute(request(Some(&retry))?).await?;
+            }
+        }
 
         debug!("Fetched NTRIP response: {:?}", res.status());
 
+        if !res.status().is_success() {
+            return Err(NtripClientError::ResponseError(format!(
+                "{:?} {}",
+                res.version(),
+                res.status()
+            )));
+        }
+
         let body = res.text().await?;
 
         let lines = body.lines().collect::<Vec<&str>>();
@@ -174,246 +284,570 @@ impl NtripClient {
         Ok(snip_info)
     }
 
+    /// Requests the sourcetable over a [Connector] connection
+    async fn fetch_sourcetable(&self) -> Result<ServerInfo, NtripClientError> {
+        let creds = self.credentials.credentials(&self.config).await?;
+        let mut authorization = preemptive_authorization(self.config.auth, &creds)?;
+        let mut challenged = false;
+
+        loop {
+            let mut sock = self.connect().await?;
+
+            let request = mount_request(&self.config, "", authorization.as_ref())?;
+            sock.write_all(request.as_bytes()).await?;
+            sock.flush().await?;
+
+            let mut raw = Vec::with_capacity(1024);
+            let head = ResponseHead::read(&mut sock, &mut raw).await?;
+
+            match head.status {
+                200 => {
+                    let mut body = BodyDecoder::new(&head);
+                    let mut text = Vec::new();
+
+                    // Read until the end of the body, or of the table
+                    loop {
+                        let done = body.decode(&mut raw, &mut text)?;
+
+                        if done
+                            || text.windows(14).any(|w| w == b"ENDSOURCETABLE")
+                            || sock.read_buf(&mut raw).await? == 0
+                        {
+                            break;
+                        }
+                    }
+
+                    let text = String::from_utf8_lossy(&text);
+                    return Ok(ServerInfo::parse(text.lines()));
+                },
+                401 => {
+                    let retry = challenge_authorization(
+                        self.config.auth,
+                        &creds,
+                        &head.challenges(),
+                        "GET",
+                        "/",
+                    )?;
+
+                    match retry {
+                        Some(retry) if !challenged && authorization.as_ref() != Some(&retry) => {
+                            debug!("Retrying with challenge response");
+                            challenged = true;
+                            authorization = Some(retry);
+                        },
+                        _ => return Err(NtripClientError::ResponseError(head.status_line)),
+                    }
+                },
+                _ => return Err(NtripClientError::ResponseError(head.status_line)),
+            }
+        }
+    }
+
     /// 'Mount' the [NtripClient] from remote $url/$mount service point.
     /// On success, you can then start listening to messages from the server.
     ///
     /// ## Input
     /// - mount: readable remote mount point (server name)
-    /// - exit_tx: [BroadcastSender] is passed to allow graceful exit on errors
     ///
     /// ## Output
     /// - [NtripHandle] which implements [Stream] to receive messages in real-time.
-    pub async fn mount(
+    ///   The connection lasts until it is closed or dropped, or the
+    ///   [CancellationToken] of this client is cancelled.
+    pub async fn mount(&mut self, mount: impl ToString) -> Result<NtripHandle, NtripClientError> {
+        self.mount_with_options(mount, MountOptions::default())
+            .await
+    }
+
+    /// 'Mount' the [NtripClient] like [Self::mount], with these [MountOptions].
+    /// Frames rejected by the [MessageFilter](crate::filter::MessageFilter)
+    /// are dropped on their header, without decoding.
+    pub async fn mount_with_options(
         &mut self,
         mount: impl ToString,
-        exit_tx: BroadcastSender<()>,
+        options: MountOptions,
     ) -> Result<NtripHandle, NtripClientError> {
+        let mount = mount.to_string();
+        let cancel = self.child_token();
+
         debug!(
             "Connecting to NTRIP server {}/{}",
             self.config.to_url(),
-            mount.to_string()
+            mount
         );
 
-        let sock = TcpStream::connect(&self.config.to_url()).await?;
+        let creds = self.credentials.credentials(&self.config).await?;
 
-        let (rx_handle, ntrip_rx) = match self.config.use_tls {
-            true => {
-                debug!("Using TLS connection");
-
-                let mut root_cert_store = rustls::RootCertStore::empty();
-                root_cert_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        if self.config.transport == Transport::Rtsp {
+            if self.connector.is_some() {
+                warn!("Connector does not apply to the RTSP transport");
+            }
+            return rtsp::mount(&self.config, &creds, &mount, cancel, options).await;
+        }
 
-                let tls_config = rustls::ClientConfig::builder()
-                    .with_root_certificates(root_cert_store)
-                    .with_no_client_auth();
-                let connector = TlsConnector::from(Arc::new(tls_config));
-                let dnsname = ServerName::try_from(self.config.host.clone())?;
+        let mut authorization = preemptive_authorization(self.config.auth, &creds)?;
+        let mut challenged = false;
 
-                let tls_sock = connector.connect(dnsname, sock).await?;
+        loop {
+            let mut sock = self.connect().await?;
 
-                Self::handle_connection(
-                    &self.config,
-                    &self.creds,
-                    &mount.to_string(),
-                    exit_tx.clone(),
-                    tls_sock,
-                )
+            match Self::request_mount(&self.config, &mount, authorization.as_ref(), &mut sock)
                 .await?
+            {
+                MountResponse::Accepted { buff, body } => {
+                    return Ok(Self::spawn_listener(
+                        &mount, buff, body, sock, cancel, options,
+                    ));
+                },
+                MountResponse::Unauthorized { status, challenges } => {
+                    // Answer the challenges once, unless it would repeat the same request
+                    let uri = format!("/{}", encode_mount(&mount));
+                    let retry = challenge_authorization(
+                        self.config.auth,
+                        &creds,
+                        &challenges,
+                        "GET",
+                        &uri,
+                    )?;
+
+                    match retry {
+                        Some(retry) if !challenged && authorization.as_ref() != Some(&retry) => {
+                            debug!("Retrying with challenge response");
+                            challenged = true;
+                            authorization = Some(retry);
+                        },
+                        _ => {
+                            error!("NTRIP server returned error: {}", status);
+                            return Err(NtripClientError::ResponseError(status));
+                        },
+                    }
+                },
+            }
+        }
+    }
+
+    /// Returns a token cancelled with the [CancellationToken] of this client, if any
+    fn child_token(&self) -> CancellationToken {
+        self.cancel
+            .as_ref()
+            .map(CancellationToken::child_token)
+            .unwrap_or_default()
+    }
+
+    /// Opens the connection to the NTRIP server through the [Connector],
+    /// TLS included
+    async fn connect(&self) -> Result<Box<dyn NtripStream>, NtripClientError> {
+        let sock = match &self.connector {
+            Some(connector) => connector.connect(&self.config).await?,
+            None => TcpConnector.connect(&self.config).await?,
+        };
+
+        match self.config.use_tls {
+            true => {
+                debug!("Using TLS connection");
+
+                let connector = self.config.tls.connector()?;
+                let dnsname = self.config.tls.server_name(&self.config.host)?;
+
+                Ok(Box::new(connector.connect(dnsname, sock).await?))
             },
             false => {
-                debug!("Using plain TCP connection");
-
-                Self::handle_connection(
-                    &self.config,
-                    &self.creds,
-                    &mount.to_string(),
-                    exit_tx.clone(),
-                    sock,
-                )
-                .await?
+                debug!("Using plain connection");
+                Ok(Box::new(sock))
             },
-        };
-
-        Ok(NtripHandle {
-            _rx_handle: rx_handle,
-            ntrip_rx,
-        })
+        }
     }
 
+    /// Mounts on an established connection, sending credentials preemptively
+    /// (see [AuthScheme](crate::auth::AuthScheme)). Challenges are not answered:
+    /// a `401` response is returned as [NtripClientError::ResponseError].
     pub async fn handle_connection(
         config: &NtripConfig,
         creds: &NtripCredentials,
         mount: &str,
-        exit_tx: BroadcastSender<()>,
         mut sock: impl AsyncRead + AsyncWrite + Unpin + Send + 'static,
-    ) -> Result<(JoinHandle<()>, UnboundedReceiver<Message>), NtripClientError> {
-        // Setup HTTP headers
-        let mut headers = HeaderMap::new();
-        headers.append(
-            USER_AGENT,
-            HeaderValue::from_str(&format!(
-                "NTRIP {}/{}",
-                env!("CARGO_PKG_NAME"),
-                env!("CARGO_PKG_VERSION")
-            ))?,
-        );
-
-        headers.append("Ntrip-Version", HeaderValue::from_static("NTRIP/2.0"));
-        headers.append("Accept", HeaderValue::from_static("*/*"));
-        headers.append("Connection", HeaderValue::from_static("close"));
-
-        // If we have credentials, add the Authorization header
-        if !creds.user.is_empty() {
-            let auth = general_purpose::STANDARD.encode(format!("{}:{}", creds.user, creds.pass));
-            headers.append(
-                "Authorization",
-                HeaderValue::from_str(&format!("Basic {}", auth))?,
-            );
+    ) -> Result<NtripHandle, NtripClientError> {
+        let authorization = preemptive_authorization(config.auth, creds)?;
+
+        match Self::request_mount(config, mount, authorization.as_ref(), &mut sock).await? {
+            MountResponse::Accepted { buff, body } => Ok(Self::spawn_listener(
+                mount,
+                buff,
+                body,
+                sock,
+                CancellationToken::new(),
+                MountOptions::default(),
+            )),
+            MountResponse::Unauthorized { status, .. } => {
+                error!("NTRIP server returned error: {}", status);
+                Err(NtripClientError::ResponseError(status))
+            },
         }
+    }
 
-        debug!("Headers: {:#?}", headers);
-
-        // Write HTTP request
+    /// Sends the mount request and reads the response head
+    async fn request_mount(
+        config: &NtripConfig,
+        mount: &str,
+        authorization: Option<&HeaderValue>,
+        sock: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    ) -> Result<MountResponse, NtripClientError> {
         debug!("Write HTTP request");
-        sock.write_all(format!("GET /{} HTTP/1.0\r\n", mount).as_bytes())
-            .await?;
-        sock.write_all(format!("Host: {}\r\n", config.to_url()).as_bytes())
-            .await?;
-
-        // Write HTTP headers
-        debug!("Writing headers");
-        for h in headers.iter() {
-            sock.write_all(format!("{}: {}\r\n", h.0.as_str(), h.1.to_str()?).as_bytes())
-                .await?;
-        }
-
-        sock.write_all(b"\r\n").await?;
+        let request = mount_request(config, mount, authorization)?;
+        sock.write_all(request.as_bytes()).await?;
         sock.flush().await?;
 
         debug!("Reading response");
         let mut buff = Vec::with_capacity(1024);
+        let head = ResponseHead::read(sock, &mut buff).await?;
 
-        // Perform a first read to get the response status
-        let n = sock.read_buf(&mut buff).await?;
-        debug!("Read {} bytes, current buffer {} bytes", n, buff.len());
-
-        // Parse out response status
-        let r = String::from_utf8_lossy(&buff[..n]);
-        match r.lines().next() {
-            Some(status) if status.contains("200 OK") => {
+        match head.status {
+            _ if head.is_success() => {
                 debug!("Got 200 OK response");
+
+                Ok(MountResponse::Accepted {
+                    body: BodyDecoder::new(&head),
+                    buff,
+                })
             },
-            Some(status) => {
-                error!("NTRIP server returned error: {}", status);
-                return Err(NtripClientError::ResponseError(status.to_string()));
-            },
-            None => {
-                error!("NTRIP server returned empty response");
-                return Err(NtripClientError::ResponseError("empty response".into()));
+            401 => Ok(MountResponse::Unauthorized {
+                challenges: head.challenges(),
+                status: head.status_line,
+            }),
+            _ => {
+                error!("NTRIP server returned error: {}", head.status_line);
+                Err(NtripClientError::ResponseError(head.status_line))
             },
         }
+    }
 
-        // Flush buffer until the first RTCM message (0xd3)
-        if let Some(i) = buff.iter().enumerate().find(|(_i, b)| **b == 0xd3) {
-            debug!(
-                "Trimming buffer to next potential frame start at index {}",
-                i.0
-            );
-            let _ = buff.drain(..i.0);
-        }
+    /// Spawns the task parsing incoming NTRIP data, until `cancel` is cancelled.
+    /// `raw` holds the body data received with the response head.
+    /// The [CloseSummary] is written when the task exits.
+    pub(crate) fn spawn_listener(
+        mount: &str,
+        mut raw: Vec<u8>,
+        mut body: BodyDecoder,
+        mut sock: impl AsyncRead + Unpin + Send + 'static,
+        cancel: CancellationToken,
+        options: MountOptions,
+    ) -> NtripHandle {
+        let (ntrip_tx, ntrip_rx) = unbounded_channel();
+        let task_cancel = cancel.clone();
+        let drain = CancellationToken::new();
+        let task_drain = drain.clone();
+        let shared = SharedState::default();
+        let task_shared = shared.clone();
+        let mount: Arc<str> = Arc::from(mount);
+        let rx_handle: JoinHandle<()> = tokio::task::spawn(async move {
+            let mut framer = RtcmFramer::default();
+            let mut sequence = 0;
+
+            // Data buffered with the response head is parsed first
+            let mut received = SystemTime::now();
+            let mut arrived = Instant::now();
+            let mut buff = Vec::with_capacity(raw.capacity());
+            let mut finished = false;
+
+            if let Err(e) = body.decode(&mut raw, &mut buff) {
+                error!("Body decoding error: {}", e);
+                return;
+            }
+            let mut bytes_received = buff.len() as u64;
+
+            loop {
+                framer.push(&buff);
+                buff.clear();
+
+                while let Some(frame) = framer.next_frame() {
+                    record(&task_shared.recorder, RecordMode::Frames, &frame, received);
+
+                    let number = message_number(&frame);
+                    if let Some(number) = number {
+                        task_shared.stream_stats.lock().unwrap().record(
+                            number,
+                            frame.len(),
+                            arrived,
+                        );
+                    }
 
-        // Spawn a task to handle incoming NTRIP data
+                    task_shared.latency.lock().unwrap().record(&frame, received);
 
-        let (ntrip_tx, ntrip_rx) = unbounded_channel();
-        let mut exit_rx = exit_tx.subscribe();
-        let rx_handle = tokio::task::spawn(async move {
-            // Track parse errors so we can drop data (or abort) if needed
-            let mut error_count = 0;
+                    // Station messages are decoded even when filtered out
+                    let accepted = options.filter.accepts_frame(&frame);
+                    let station = number.is_some_and(is_station_message);
+
+                    if !accepted && !station {
+                        trace!("Filtered out RTCM frame ({} bytes)", frame.len());
+                        continue;
+                    }
+
+                    match MessageFrame::new(&frame) {
+                        Ok(f) => {
+                            let m = f.get_message();
+                            debug!("Parsed RTCM message: {:?} ({} bytes)", m, frame.len());
+
+                            if station {
+                                task_shared.update_station(&m);
+                            }
+
+                            if !accepted {
+                                continue;
+                            }
+
+                            let envelope = Envelope {
+                                message: m,
+                                frame,
+                                mount: mount.clone(),
+                                sequence,
+                                received,
+                                instant: arrived,
+                            };
+                            sequence += 1;
+
+                            if ntrip_tx.send(envelope).is_err() {
+                                debug!("NTRIP handle dropped");
+                                finished = true;
+                                break;
+                            }
+                        },
+                        Err(e) => warn!("RTCM decoding error: {}", e),
+                    }
+                }
+
+                let stats = framer.stats();
+                *task_shared.framer_stats.lock().unwrap() = stats;
+
+                if stats.skipped_bytes > 0 {
+                    trace!(
+                        "{} bytes skipped so far ({} CRC failures)",
+                        stats.skipped_bytes,
+                        stats.crc_failures
+                    );
+                }
+
+                if finished {
+                    debug!("End of stream");
+                    break;
+                }
 
-            'listener: loop {
                 select! {
-                    n = sock.read_buf(&mut buff) => match n {
+                    n = sock.read_buf(&mut raw) => match n {
                         Ok(n) => {
-                            debug!("Read {} bytes, current buffer {} bytes", n, buff.len());
-                            trace!("Appended {:02x?}", &buff[buff.len()-n..][..n]);
+                            debug!("Read {} bytes, {} bytes buffered", n, framer.buffered());
+                            trace!("Appended {:02x?}", &raw[raw.len()-n..][..n]);
 
                             // Handle zero length read (connection closed)
                             if n == 0 {
                                 warn!("Zero length response");
-                                break 'listener;
+                                break;
                             }
 
-                            // Trim any non-message data from the start of the buffer
-                            if buff[0] != 0xd3 {
-                                if let Some(i) = buff.iter().enumerate().find(|(_i, b)| **b == 0xd3) {
-                                    warn!("Trimming buffer to next potential frame start at index {}", i.0);
-                                    buff.drain(..i.0);
+                            received = SystemTime::now();
+                            arrived = Instant::now();
 
-                                    assert_eq!(buff[0], 0xd3);
-                                }
+                            let decoded = buff.len();
+                            match body.decode(&mut raw, &mut buff) {
+                                Ok(done) => finished = done,
+                                Err(e) => {
+                                    error!("Body decoding error: {}", e);
+                                    break;
+                                },
                             }
 
-                            // While we have enough data for a header,
-                            // parse out RTCM messages
-                            while buff.len() > 6 {
-                                // Attempt to parse frames
-                                match MessageFrame::new(&buff[..]) {
-                                    Ok(f) => {
-                                        // Parse out message from frame
-                                        let m = f.get_message();
-
-                                        debug!("Parsed RTCM message: {:?} (consumed {} bytes)", m, f.frame_len());
-
-                                        // Emit message
-                                        ntrip_tx.send(m).unwrap();
-
-                                        // Remove parsed data from the buffer
-                                        let _ = buff.drain(..f.frame_len());
-
-                                        // Reset error counter
-                                        error_count = 0;
-                                    },
-                                    Err(e) => {
-                                        warn!("RTCM parse error: {} (count: {})", e, error_count);
-
-                                        // Update error counter
-                                        error_count += 1;
-
-                                        // If we keep getting errors, abort the connection
-                                        if error_count >= 5 {
-                                            error!("Too many parse errors, closing connection");
-                                            break 'listener;
-                                        }
-
-                                        break;
-                                    }
-                                }
-                            }
+                            bytes_received += (buff.len() - decoded) as u64;
+                            record(&task_shared.recorder, RecordMode::Raw, &buff[decoded..], received);
                         },
                         Err(e) => {
                             error!("socket read error: {}", e);
                             break;
                         },
                     },
-                    _ = exit_rx.recv() => {
-                        error!("Exiting NTRIP read loop on signal");
+                    _ = task_cancel.cancelled() => {
+                        debug!("NTRIP mount closed");
                         break;
-                    }
+                    },
+                    // Stop reading, deliver the complete frames still buffered
+                    _ = task_drain.cancelled() => {
+                        debug!("Closing NTRIP mount gracefully");
+                        finished = true;
+                    },
                 }
             }
 
             warn!("NTRIP read loop exiting");
 
-            if !buff.is_empty() {
-                warn!("Dropping {} bytes of unparsed data", buff.len());
+            if framer.buffered() > 0 {
+                warn!("Dropping {} bytes of unparsed data", framer.buffered());
+            }
 
-                if let Ok(s) = String::from_utf8(buff) {
-                    debug!("Unparsed data:\r\n{}", s);
-                }
+            let stats = framer.stats();
+            *task_shared.framer_stats.lock().unwrap() = stats;
+
+            *task_shared.summary.lock().unwrap() = CloseSummary {
+                bytes_received,
+                frames_delivered: sequence,
+                bytes_discarded: stats.skipped_bytes + framer.buffered() as u64,
+            };
+
+            if let Some(r) = task_shared.recorder.lock().unwrap().as_ref() {
+                r.flush();
             }
         });
 
-        Ok((rx_handle, ntrip_rx))
+        NtripHandle::new(rx_handle, ntrip_rx, shared, cancel, drain)
+    }
+}
+
+/// Response to a mount request
+enum MountResponse {
+    /// Mount accepted, with the data received after the response head
+    Accepted { buff: Vec<u8>, body: BodyDecoder },
+    /// Authentication required
+    Unauthorized {
+        status: String,
+        challenges: Vec<AuthChallenge>,
+    },
+}
+
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+fn bind_interface(
+    builder: reqwest::ClientBuilder,
+    interface: &str,
+) -> Result<reqwest::ClientBuilder, NtripClientError> {
+    Ok(builder.interface(interface))
+}
+
+#[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+fn bind_interface(
+    _: reqwest::ClientBuilder,
+    _: &str,
+) -> Result<reqwest::ClientBuilder, NtripClientError> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "binding to a network interface is not supported on this platform",
+    )
+    .into())
+}
+
+/// Hands received data over to the active [Recorder], if it captures this [RecordMode]
+pub(crate) fn record(
+    recorder: &Mutex<Option<RecorderWriter>>,
+    mode: RecordMode,
+    chunk: &[u8],
+    received: SystemTime,
+) {
+    if let Some(r) = recorder.lock().unwrap().as_ref() {
+        r.write(mode, chunk, received);
+    }
+}
+
+impl NtripHandle {
+    pub(crate) fn new(
+        rx_handle: JoinHandle<()>,
+        ntrip_rx: UnboundedReceiver<Envelope>,
+        shared: SharedState,
+        cancel: CancellationToken,
+        drain: CancellationToken,
+    ) -> Self {
+        Self {
+            rx_handle: Some(rx_handle),
+            ntrip_rx,
+            shared,
+            cancel,
+            drain,
+        }
+    }
+
+    /// Closes the connection and waits for the reader task to exit.
+    /// Messages not consumed yet are dropped.
+    pub async fn close(mut self) -> CloseSummary {
+        self.cancel.cancel();
+        self.join().await
+    }
+
+    /// Stops reading and waits for the complete frames still buffered to be
+    /// parsed: their messages remain available from this [Stream], which then ends.
+    /// Active recordings are flushed.
+    pub async fn close_gracefully(&mut self) -> CloseSummary {
+        self.drain.cancel();
+        self.join().await
+    }
+
+    /// Waits for the reader task to exit and returns its [CloseSummary]
+    async fn join(&mut self) -> CloseSummary {
+        if let Some(rx_handle) = self.rx_handle.take() {
+            if let Err(e) = rx_handle.await {
+                error!("NTRIP reader task failed: {}", e);
+            }
+        }
+
+        *self.shared.summary.lock().unwrap()
+    }
+
+    /// Returns the [RtcmFramer] counters of this stream
+    /// (CRC failures, skipped bytes..)
+    pub fn framer_stats(&self) -> FramerStats {
+        *self.shared.framer_stats.lock().unwrap()
+    }
+
+    /// Returns a snapshot of the [StreamStats] of this stream
+    /// (per message counts, intervals, jitter..), see [StreamStats::compare]
+    /// to check them against the sourcetable
+    pub fn stream_stats(&self) -> StreamStats {
+        self.shared.stream_stats.lock().unwrap().clone()
+    }
+
+    /// Returns the [LatencyStats] of this stream: age of the observation
+    /// epochs when received, see the [latency](crate::latency) module
+    pub fn latency_stats(&self) -> LatencyStats {
+        self.shared.latency.lock().unwrap().clone()
+    }
+
+    /// Returns the [ReferenceStation] described by the stream (RTCM 1005 to 1008, 1033),
+    /// None until one of these messages was received.
+    /// See [ReferenceStation::location_mismatch] to check it against the sourcetable.
+    pub fn reference_station(&self) -> Option<ReferenceStation> {
+        self.shared.station.lock().unwrap().clone()
+    }
+
+    /// Turns this handle into a [Stream] of [Envelope]s: messages with their
+    /// receive time, mount name, sequence number and raw frame
+    pub fn into_envelopes(self) -> NtripEnvelopes {
+        NtripEnvelopes(self)
+    }
+
+    /// Starts recording this mount with the provided [Recorder], written on
+    /// its own thread. Returns the previously active [Recorder], if any,
+    /// once its pending data is written.
+    pub async fn record(&self, recorder: Recorder) -> Option<Recorder> {
+        let previous = self
+            .shared
+            .recorder
+            .lock()
+            .unwrap()
+            .replace(RecorderWriter::spawn(recorder));
+
+        match previous {
+            Some(writer) => writer.finish().await,
+            None => None,
+        }
+    }
+
+    /// Stops recording this mount. Returns the active [Recorder],
+    /// once its pending data is written and flushed.
+    pub async fn stop_recording(&self) -> Option<Recorder> {
+        let writer = self.shared.recorder.lock().unwrap().take();
+
+        match writer {
+            Some(writer) => writer.finish().await,
+            None => None,
+        }
+    }
+}
+
+impl Drop for NtripHandle {
+    fn drop(&mut self) {
+        self.cancel.cancel();
     }
 }
 
@@ -425,19 +859,44 @@ impl Stream for NtripHandle {
         mut self: std::pin::Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Option<Self::Item>> {
-        self.ntrip_rx.poll_recv(cx)
+        self.ntrip_rx.poll_recv(cx).map(|e| e.map(|e| e.message))
+    }
+}
+
+impl NtripEnvelopes {
+    /// Returns the underlying [NtripHandle], for statistics and recording
+    pub fn handle(&self) -> &NtripHandle {
+        &self.0
+    }
+}
+
+impl Stream for NtripEnvelopes {
+    type Item = Envelope;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.0.ntrip_rx.poll_recv(cx)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::env;
+    use std::{env, time::Duration};
 
     use futures::StreamExt;
+    use rtcm_rs::{msg::Msg1006T, MessageBuilder};
+    use tokio::time::timeout;
     use tracing::debug;
 
     use super::*;
-    use crate::config::NtripCredentials;
+    use crate::{
+        auth::AuthScheme,
+        config::NtripCredentials,
+        mock::{station_frame, MockCaster, MockMount},
+        snip::MountInfo,
+    };
 
     fn setup_logging() {
         let _ = tracing_subscriber::FmtSubscriber::builder()
@@ -447,6 +906,424 @@ mod tests {
             .try_init();
     }
 
+    #[tokio::test]
+    async fn test_mock_list_mounts() {
+        setup_logging();
+
+        let info = MountInfo::parse(
+            "STR;VALDM;Valence;RTCM 3.2;1005(10),1077(1);;GPS;SNIP;FRA;44.93;4.89",
+        )
+        .unwrap();
+        let caster = MockCaster::default()
+            .with_source(&info)
+            .start()
+            .await
+            .unwrap();
+
+        let mut client = NtripClient::new(caster.config(), NtripCredentials::default())
+            .await
+            .unwrap();
+
+        let server_info = client.list_mounts().await.unwrap();
+        assert_eq!(server_info.services, vec![info.clone()]);
+
+        // Wrong credentials, after the challenge response
+        let creds = NtripCredentials::default()
+            .with_username("user")
+            .with_password("pass");
+        let caster = MockCaster::default()
+            .with_credentials(&creds)
+            .with_auth_scheme(AuthScheme::Digest)
+            .with_source(&info)
+            .start()
+            .await
+            .unwrap();
+
+        let mut client = NtripClient::new(caster.config(), creds.with_password("wrong"))
+            .await
+            .unwrap();
+
+        match client.list_mounts().await {
+            Err(NtripClientError::ResponseError(status)) => assert!(status.contains("401")),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(caster.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_mock_mount_icy() {
+        setup_logging();
+
+        // A corrupted frame in between must not end the stream
+        let mut corrupted = station_frame(3);
+        corrupted[6] ^= 0x10;
+
+        let mut data = station_frame(1);
+        data.extend(corrupted);
+        data.extend(station_frame(2));
+
+        let caster = MockCaster::default()
+            .with_mount("VALDM", MockMount::icy().with_data(&data).then_close())
+            .start()
+            .await
+            .unwrap();
+
+        let mut client = NtripClient::new(caster.config(), NtripCredentials::default())
+            .await
+            .unwrap();
+
+        let mut h = client.mount("VALDM").await.unwrap();
+
+        let stations = (&mut h)
+            .map(|m| match m {
+                Message::Msg1005(m) => m.reference_station_id,
+                m => panic!("unexpected message {:?}", m),
+            })
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(stations, vec![1, 2]);
+        assert!(h.framer_stats().crc_failures >= 1);
+
+        let requests = caster.requests();
+        assert_eq!(requests[0].path, "/VALDM");
+        assert_eq!(requests[0].header("Ntrip-Version"), Some("Ntrip/2.0"));
+    }
+
+    #[tokio::test]
+    async fn test_mock_mount_filter() {
+        setup_logging();
+
+        let mut antenna = MessageBuilder::new();
+        let antenna = antenna
+            .build_message(&Message::Msg1006(Msg1006T {
+                reference_station_id: 2,
+                ..Default::default()
+            }))
+            .unwrap()
+            .to_vec();
+
+        let mut data = station_frame(1);
+        data.extend(antenna);
+        data.extend(station_frame(3));
+
+        let caster = MockCaster::default()
+            .with_mount("VALDM", MockMount::icy().with_data(&data).then_close())
+            .start()
+            .await
+            .unwrap();
+
+        let mut client = NtripClient::new(caster.config(), NtripCredentials::default())
+            .await
+            .unwrap();
+
+        let options = MountOptions::default().with_filter("1006-1008".parse().unwrap());
+        let mut h = client.mount_with_options("VALDM", options).await.unwrap();
+
+        let stations = (&mut h)
+            .map(|m| match m {
+                Message::Msg1006(m) => m.reference_station_id,
+                m => panic!("unexpected message {:?}", m),
+            })
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(stations, vec![2]);
+
+        // Statistics account for filtered out frames too
+        let stats = h.stream_stats();
+        assert_eq!(stats.frames, 3);
+        assert_eq!(stats.message(1005).map(|m| m.count), Some(2));
+
+        // So does the reference station, from the last station message
+        let station = h.reference_station().unwrap();
+        assert_eq!(station.station_id, 3);
+        assert!(station.position.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_mock_mount_chunked() {
+        setup_logging();
+
+        let (first, second) = (station_frame(1), station_frame(2));
+
+        // Frames split across chunks and reads
+        let caster = MockCaster::default()
+            .with_mount(
+                "VAL DM",
+                MockMount::chunked()
+                    .with_data(&first[..4])
+                    .with_delay(Duration::from_millis(20))
+                    .with_data(&first[4..])
+                    .with_data(&second)
+                    .then_close(),
+            )
+            .start()
+            .await
+            .unwrap();
+
+        let mut client = NtripClient::new(caster.config(), NtripCredentials::default())
+            .await
+            .unwrap();
+
+        let h = client.mount("VAL DM").await.unwrap();
+
+        let envelopes = h.into_envelopes().collect::<Vec<_>>().await;
+        let stations = envelopes
+            .iter()
+            .map(|e| match &e.message {
+                Message::Msg1005(m) => m.reference_station_id,
+                m => panic!("unexpected message {:?}", m),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(stations, vec![1, 2]);
+
+        for (i, (envelope, frame)) in envelopes.iter().zip([first, second]).enumerate() {
+            assert_eq!(&*envelope.mount, "VAL DM");
+            assert_eq!(envelope.sequence, i as u64);
+            assert_eq!(envelope.frame, frame);
+        }
+        assert!(envelopes[0].instant <= envelopes[1].instant);
+
+        let requests = caster.requests();
+        assert_eq!(requests[0].request_line, "GET /VAL%20DM HTTP/1.1");
+        assert_eq!(
+            requests[0].header("Host"),
+            Some(caster.config().to_url().as_str())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mock_mount_errors() {
+        setup_logging();
+
+        let creds = NtripCredentials::default()
+            .with_username("user")
+            .with_password("pass");
+
+        let caster = MockCaster::default()
+            .with_credentials(&creds)
+            .with_mount("VALDM", MockMount::icy().then_stall())
+            .with_mount("DOWN", MockMount::status(503, "Service Unavailable"))
+            .with_mount("JUNK", MockMount::garbage(b"\x00\x01\x02"))
+            .start()
+            .await
+            .unwrap();
+
+        let mut client = NtripClient::new(caster.config(), creds.with_password("wrong"))
+            .await
+            .unwrap();
+
+        match client.mount("VALDM").await {
+            Err(NtripClientError::ResponseError(status)) => assert!(status.contains("401")),
+            r => panic!("expected 401 error, got {:?}", r.err()),
+        }
+
+        let mut client = NtripClient::new(caster.config(), creds).await.unwrap();
+
+        for (mount, expected) in [("UNKNOWN", "404"), ("DOWN", "503"), ("JUNK", "")] {
+            match client.mount(mount).await {
+                Err(NtripClientError::ResponseError(status)) => assert!(status.contains(expected)),
+                r => panic!("expected {} error, got {:?}", mount, r.err()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mock_mount_auth() {
+        setup_logging();
+
+        let creds = NtripCredentials::default()
+            .with_username("user")
+            .with_password("pass")
+            .with_token("token");
+
+        for (scheme, prefix) in [
+            (AuthScheme::Digest, "Digest "),
+            (AuthScheme::Bearer, "Bearer "),
+        ] {
+            let caster = MockCaster::default()
+                .with_credentials(&creds)
+                .with_auth_scheme(scheme)
+                .with_mount("VALDM", MockMount::icy().with_data(&station_frame(1)))
+                .start()
+                .await
+                .unwrap();
+
+            let mut client = NtripClient::new(caster.config(), creds.clone())
+                .await
+                .unwrap();
+            let mut handle = client.mount("VALDM").await.unwrap();
+            assert!(handle.next().await.is_some());
+
+            let requests = caster.requests();
+            let authorization = requests.last().unwrap().header("Authorization").unwrap();
+            assert!(authorization.starts_with(prefix));
+        }
+
+        // Digest URI matches the escaped request line
+        let caster = MockCaster::default()
+            .with_credentials(&creds)
+            .with_auth_scheme(AuthScheme::Digest)
+            .with_mount("VAL DM", MockMount::icy().with_data(&station_frame(1)))
+            .start()
+            .await
+            .unwrap();
+
+        let mut client = NtripClient::new(caster.config(), creds.clone())
+            .await
+            .unwrap();
+        let mut handle = client.mount("VAL DM").await.unwrap();
+        assert!(handle.next().await.is_some());
+
+        // Digest required, but Basic only allowed
+        let caster = MockCaster::default()
+            .with_credentials(&creds)
+            .with_auth_scheme(AuthScheme::Digest)
+            .with_mount("VALDM", MockMount::icy().then_stall())
+            .start()
+            .await
+            .unwrap();
+
+        let config = caster.config().with_auth_scheme(AuthScheme::Basic);
+        let mut client = NtripClient::new(config, creds).await.unwrap();
+
+        match client.mount("VALDM").await {
+            Err(NtripClientError::ResponseError(status)) => assert!(status.contains("401")),
+            r => panic!("expected 401 error, got {:?}", r.err()),
+        }
+        assert_eq!(caster.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_mock_connector() {
+        setup_logging();
+
+        let creds = NtripCredentials::default()
+            .with_username("user")
+            .with_password("pass");
+        let info = MountInfo::parse("STR;VALDM;Valence;RTCM 3.2;1005(10);;GPS;SNIP;FRA;44.93;4.89")
+            .unwrap();
+
+        let caster = MockCaster::default()
+            .with_credentials(&creds)
+            .with_auth_scheme(AuthScheme::Digest)
+            .with_source(&info)
+            .with_mount("VALDM", MockMount::icy().with_data(&station_frame(1)))
+            .start()
+            .await
+            .unwrap();
+
+        // Unresolvable host: only reachable through the connector
+        let config = caster.config().with_host("gateway.invalid");
+        let mut client = NtripClient::new(config, creds)
+            .await
+            .unwrap()
+            .with_connector(caster.connector());
+
+        let server_info = client.list_mounts().await.unwrap();
+        assert_eq!(server_info.services, vec![info]);
+
+        let mut handle = client.mount("VALDM").await.unwrap();
+        assert!(handle.next().await.is_some());
+
+        let requests = caster.requests();
+        assert_eq!(requests.len(), 4);
+        let host = format!("gateway.invalid:{}", caster.addr().port());
+        assert_eq!(requests[0].header("Host"), Some(host.as_str()));
+    }
+
+    #[tokio::test]
+    async fn test_mock_mount_interrupted() {
+        setup_logging();
+
+        let frame = station_frame(1);
+
+        let caster = MockCaster::default()
+            .with_mount(
+                "CUT",
+                MockMount::icy()
+                    .with_data(&frame)
+                    .with_data(&frame[..10])
+                    .then_abort(),
+            )
+            .with_mount("STALL", MockMount::icy().with_data(&frame).then_stall())
+            .start()
+            .await
+            .unwrap();
+
+        let mut client = NtripClient::new(caster.config(), NtripCredentials::default())
+            .await
+            .unwrap();
+
+        // Closed mid-frame: the complete frame is delivered, then the stream ends
+        let h = client.mount("CUT").await.unwrap();
+        assert_eq!(h.count().await, 1);
+
+        // Stalled: nothing more arrives until cancelled
+        let cancel = CancellationToken::new();
+        let mut client = client.with_cancellation_token(cancel.clone());
+        let mut h = client.mount("STALL").await.unwrap();
+        assert!(h.next().await.is_some());
+        assert!(timeout(Duration::from_millis(200), h.next()).await.is_err());
+
+        cancel.cancel();
+        assert!(h.next().await.is_none());
+
+        // Closed by the handle
+        let h = client.mount("STALL").await.unwrap();
+        timeout(Duration::from_secs(5), h.close()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_mock_mount_graceful_close() {
+        setup_logging();
+
+        // Two frames and the start of a third, then nothing
+        let frame = station_frame(1);
+        let data = [&frame[..], &frame[..], &frame[..10]].concat();
+
+        // Chunked transfer framing is not counted
+        let caster = MockCaster::default()
+            .with_mount("VALDM", MockMount::icy().with_data(&data).then_stall())
+            .with_mount(
+                "CHUNKED",
+                MockMount::chunked().with_data(&data).then_stall(),
+            )
+            .start()
+            .await
+            .unwrap();
+
+        let mut client = NtripClient::new(caster.config(), NtripCredentials::default())
+            .await
+            .unwrap();
+
+        for mount in ["VALDM", "CHUNKED"] {
+            let mut h = client.mount(mount).await.unwrap();
+
+            timeout(Duration::from_secs(5), async {
+                while h.stream_stats().frames < 2 {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .unwrap();
+
+            let summary = timeout(Duration::from_secs(5), h.close_gracefully())
+                .await
+                .unwrap();
+            assert_eq!(
+                summary,
+                CloseSummary {
+                    bytes_received: data.len() as u64,
+                    frames_delivered: 2,
+                    bytes_discarded: 10,
+                }
+            );
+
+            // Messages received before closing are still delivered
+            assert_eq!(h.count().await, 2);
+        }
+    }
+
     #[tokio::test]
     #[ignore = "Requires NTRIP config from the environment"]
     async fn test_ntrip_client() {
@@ -454,8 +1331,6 @@ mod tests {
 
         debug!("Connecting to NTRIP server");
 
-        let (exit_tx, _exit_rx) = tokio::sync::broadcast::channel(1);
-
         let mount = env::var("NTRIP_MOUNT").unwrap_or("ARGOACU".to_string());
         let config = env::var("NTRIP_HOST")
             .unwrap_or("rtk2go".to_string())
@@ -464,20 +1339,18 @@ mod tests {
         let creds = NtripCredentials {
             user: env::var("NTRIP_USER").unwrap_or("user".into()),
             pass: env::var("NTRIP_PASS").unwrap_or("pass".into()),
+            ..Default::default()
         };
 
         let mut client = NtripClient::new(config, creds).await.unwrap();
 
-        let mut h = client
-            .mount(mount.to_string(), exit_tx.clone())
-            .await
-            .unwrap();
+        let mut h = client.mount(mount.to_string()).await.unwrap();
 
         for _i in 0..10 {
             let m = h.next().await.unwrap();
             debug!("Got RTCM message: {:?}", m);
         }
 
-        let _ = exit_tx.send(());
+        h.close().await;
     }
 }
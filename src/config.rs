@@ -1,17 +1,25 @@
 //! NTRIP client configuration objects
 
-use std::str::FromStr;
+use std::{fmt, net::IpAddr, str::FromStr};
 
 use strum::{Display, EnumString, VariantNames};
 
-use crate::NtripClientError;
+use crate::{
+    auth::AuthScheme,
+    filter::MessageFilter,
+    proxy::{format_host, ProxyConfig},
+    socket::SocketOptions,
+    tls::TlsSettings,
+    url::{scheme, NtripUrl},
+    NtripClientError,
+};
 
 /// NTRIP (Networked Transport of RTCM via Internet Protocol) configuration
 #[derive(Clone, PartialEq, Debug)]
 #[cfg_attr(feature = "clap", derive(clap::Parser))]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NtripConfig {
-    /// Host name or IP address of the NTRIP server
+    /// Host name or IP address of the NTRIP server (IPv6 without brackets)
     #[cfg_attr(
         feature = "clap",
         clap(long = "ntrip-host", env = "NTRIP_HOST", default_value = "rtk2go.com")
@@ -31,6 +39,58 @@ pub struct NtripConfig {
         clap(long = "ntrip-use-tls", env = "NTRIP_USE_TLS", default_value_t = false)
     )]
     pub use_tls: bool,
+
+    /// Proxy used to reach the NTRIP server ("http://[user:pass@]host:port" or "socks5://..."),
+    /// the environment proxy settings apply when not set
+    #[cfg_attr(feature = "clap", clap(long = "ntrip-proxy", env = "NTRIP_PROXY"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub proxy: Option<ProxyConfig>,
+
+    /// TLS settings, used when TLS is active
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub tls: TlsSettings,
+
+    /// Authentication scheme ("auto", "none", "basic", "digest" or "bearer")
+    #[cfg_attr(
+        feature = "clap",
+        clap(long = "ntrip-auth", env = "NTRIP_AUTH", default_value = "auto")
+    )]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub auth: AuthScheme,
+
+    /// Transport of the mount data ("tcp", or "rtsp" for RTSP/RTP)
+    #[cfg_attr(
+        feature = "clap",
+        clap(
+            long = "ntrip-transport",
+            env = "NTRIP_TRANSPORT",
+            default_value = "tcp"
+        )
+    )]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub transport: Transport,
+
+    /// Local address to connect from, selecting the network to use
+    #[cfg_attr(
+        feature = "clap",
+        clap(long = "ntrip-local-address", env = "NTRIP_LOCAL_ADDRESS")
+    )]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub local_address: Option<IpAddr>,
+
+    /// Network interface to connect through, e.g. "wwan0" (Linux only)
+    #[cfg_attr(
+        feature = "clap",
+        clap(long = "ntrip-interface", env = "NTRIP_INTERFACE")
+    )]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub interface: Option<String>,
+
+    /// TCP socket options
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub socket: SocketOptions,
 }
 
 impl Default for NtripConfig {
@@ -42,9 +102,9 @@ impl Default for NtripConfig {
 }
 
 impl NtripConfig {
-    /// Generate a connection URL ("host:port") from the NtripConfig
+    /// Generate a connection URL ("host:port", "[ipv6]:port") from the NtripConfig
     pub fn to_url(&self) -> String {
-        format!("{}:{}", self.host, self.port)
+        format!("{}:{}", format_host(&self.host), self.port)
     }
 
     /// Prepares an [NtripConfig] for one of our predefined [RtcmProvider]s
@@ -53,6 +113,13 @@ impl NtripConfig {
             host: network.host().to_string(),
             port: network.port(),
             use_tls: network.uses_tls(),
+            proxy: None,
+            tls: TlsSettings::default(),
+            auth: AuthScheme::default(),
+            transport: Transport::default(),
+            local_address: None,
+            interface: None,
+            socket: SocketOptions::default(),
         }
     }
 
@@ -83,11 +150,109 @@ impl NtripConfig {
         s.use_tls = false;
         s
     }
+
+    /// Copies and returns [NtripConfig] connecting through this [ProxyConfig]
+    pub fn with_proxy(&self, proxy: ProxyConfig) -> Self {
+        let mut s = self.clone();
+        s.proxy = Some(proxy);
+        s
+    }
+
+    /// Copies and returns [NtripConfig] with TLS/SSL active, using these [TlsSettings]
+    pub fn with_tls_settings(&self, tls: TlsSettings) -> Self {
+        let mut s = self.clone();
+        s.use_tls = true;
+        s.tls = tls;
+        s
+    }
+
+    /// Copies and returns [NtripConfig] using this [AuthScheme]
+    pub fn with_auth_scheme(&self, auth: AuthScheme) -> Self {
+        let mut s = self.clone();
+        s.auth = auth;
+        s
+    }
+
+    /// Copies and returns [NtripConfig] using this [Transport]
+    pub fn with_transport(&self, transport: Transport) -> Self {
+        let mut s = self.clone();
+        s.transport = transport;
+        s
+    }
+
+    /// Copies and returns [NtripConfig] connecting from this local address
+    pub fn with_local_address(&self, address: IpAddr) -> Self {
+        let mut s = self.clone();
+        s.local_address = Some(address);
+        s
+    }
+
+    /// Copies and returns [NtripConfig] connecting through this network interface
+    pub fn with_interface(&self, interface: &str) -> Self {
+        let mut s = self.clone();
+        s.interface = Some(interface.to_string());
+        s
+    }
+
+    /// Copies and returns [NtripConfig] with these TCP [SocketOptions]
+    pub fn with_socket_options(&self, socket: SocketOptions) -> Self {
+        let mut s = self.clone();
+        s.socket = socket;
+        s
+    }
+
+    /// Returns the [ProxyConfig] to use: the configured one,
+    /// or the one defined by the environment (see [ProxyConfig::from_env]).
+    pub fn effective_proxy(&self) -> Option<ProxyConfig> {
+        self.proxy
+            .clone()
+            .or_else(|| ProxyConfig::from_env(&self.host, self.use_tls))
+    }
+}
+
+/// Transport of the mount data
+#[derive(Clone, Copy, Default, PartialEq, Debug, EnumString, Display, VariantNames)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Transport {
+    /// HTTP request, data streamed on the same TCP (or TLS) connection
+    #[default]
+    #[strum(serialize = "tcp")]
+    Tcp,
+    /// NTRIP 2.0 RTSP control connection, data received as RTP over UDP
+    #[strum(serialize = "rtsp")]
+    Rtsp,
 }
 
-/// Credentials for an NTRIP (RTCM) service
+/// Options applying to a single mount
 #[derive(Clone, Default, PartialEq, Debug)]
 #[cfg_attr(feature = "clap", derive(clap::Parser))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MountOptions {
+    /// RTCM messages to deliver, e.g. `1005,1006,1074-1127,!1230`.
+    /// Other frames are dropped before decoding.
+    #[cfg_attr(
+        feature = "clap",
+        clap(long = "ntrip-messages", env = "NTRIP_MESSAGES", default_value = "")
+    )]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub filter: MessageFilter,
+}
+
+impl MountOptions {
+    /// Copies and returns [MountOptions] using this [MessageFilter]
+    pub fn with_filter(&self, filter: MessageFilter) -> Self {
+        let mut s = self.clone();
+        s.filter = filter;
+        s
+    }
+}
+
+/// Credentials for an NTRIP (RTCM) service.
+/// Secrets are redacted from the [Debug] output.
+#[derive(Clone, Default, PartialEq)]
+#[cfg_attr(feature = "clap", derive(clap::Parser))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct NtripCredentials {
     /// Username for the NTRIP service
     #[cfg_attr(feature = "clap", clap(long = "ntrip-user", env = "NTRIP_USER"))]
@@ -99,6 +264,10 @@ pub struct NtripCredentials {
         clap(long = "ntrip-pass", env = "NTRIP_PASS", default_value = "")
     )]
     pub pass: String,
+
+    /// Bearer token for the NTRIP service
+    #[cfg_attr(feature = "clap", clap(long = "ntrip-token", env = "NTRIP_TOKEN"))]
+    pub token: Option<String>,
 }
 
 impl NtripCredentials {
@@ -115,6 +284,13 @@ impl NtripCredentials {
         s.pass = password.to_string();
         s
     }
+
+    /// Copies and returns [NtripCredentials] with a Bearer token
+    pub fn with_token(&self, token: &str) -> Self {
+        let mut s = self.clone();
+        s.token = Some(token.to_string());
+        s
+    }
 }
 
 /// Common RTCM data providers
@@ -170,7 +346,8 @@ impl RtcmProvider {
     }
 }
 
-/// Parse an [NtripConfig] from a URL string
+/// Parse an [NtripConfig] from a URL string, the scheme selecting TLS
+/// and transport, see [NtripUrl] for credentials, mount and query.
 ///
 /// For example:
 /// ```
@@ -183,6 +360,18 @@ impl RtcmProvider {
 /// assert_eq!(cfg.use_tls, false);
 /// ```
 ///
+/// IPv6 addresses are written within brackets:
+/// ```
+/// # use ntrip_client::config::NtripConfig;
+///
+/// let cfg = "ntrip://[2001:db8::1]:2102".parse::<NtripConfig>().unwrap();
+///
+/// assert_eq!(cfg.host, "2001:db8::1");
+/// assert_eq!(cfg.port, 2102);
+/// assert_eq!(cfg.to_url(), "[2001:db8::1]:2102");
+/// assert_eq!(cfg.to_string(), "ntrip://[2001:db8::1]:2102");
+/// ```
+///
 /// This also matches on [RtcmProvider]'s for convenience.
 /// ```
 /// # use ntrip_client::config::NtripConfig;
@@ -196,46 +385,17 @@ impl RtcmProvider {
 impl FromStr for NtripConfig {
     type Err = NtripClientError;
 
-    /// Parse an [NtripConfig] from a URL string
+    /// Parse an [NtripConfig] from a URL string, see [NtripUrl]
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        // Match on known providers
-        if let Ok(provider) = RtcmProvider::from_str(s) {
-            return Ok(NtripConfig::from_provider(provider));
-        }
+        s.parse::<NtripUrl>().map(|url| url.config)
+    }
+}
 
-        // Strip protocol if present
-        let proto = if s.starts_with("http://") {
-            "http"
-        } else if s.starts_with("https://") {
-            "https"
-        } else if s.starts_with("ntrip://") {
-            "ntrip"
-        } else {
-            "unknown"
-        };
-        let s = s.trim_start_matches(&format!("{proto}://"));
-
-        // Split host and port
-        let parts: Vec<&str> = s.split(':').collect();
-        if parts.is_empty() {
-            return Err(NtripClientError::InvalidUrl);
-        }
-        let host = parts[0].to_string();
-
-        // Parse port or use default
-        let port = if parts.len() > 1 {
-            parts[1]
-                .parse::<u16>()
-                .map_err(|_| NtripClientError::InvalidPort)?
-        } else if proto == "https" {
-            443
-        } else {
-            2101
-        };
-        Ok(NtripConfig {
-            host,
-            port,
-            use_tls: port == 443,
-        })
+/// Formats an [NtripConfig] as a URL ("ntrip://host:port", "ntrips://..."
+/// with TLS, "rtsp://..." for RTSP), parsing back to the same host, port,
+/// TLS and transport
+impl fmt::Display for NtripConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}://{}", scheme(self), self.to_url())
     }
 }
@@ -0,0 +1,87 @@
+//! Transport connectors
+//!
+//! [NtripClient](crate::NtripClient) opens its connections to the caster
+//! through a [Connector]: [TcpConnector] by default, connecting directly or
+//! through the configured proxy. Applications can supply their own transport
+//! (Unix socket to a local gateway, tunnel, modem bridge, in-memory stream..)
+//! with [NtripClient::with_connector](crate::NtripClient::with_connector).
+//! TLS is layered on top of the connection when the [NtripConfig] requires it.
+//!
+//! ```no_run
+//! use ntrip_client::{NtripClient, NtripClientError, NtripConfig, NtripCredentials};
+//! use tokio::net::UnixStream;
+//!
+//! # async fn run() -> Result<(), NtripClientError> {
+//! let config = NtripConfig::default().with_host("gateway").without_tls();
+//!
+//! let mut client = NtripClient::new(config, NtripCredentials::default())
+//!     .await?
+//!     .with_connector(|_: &NtripConfig| async {
+//!         UnixStream::connect("/run/ntrip-gateway.sock")
+//!             .await
+//!             .map_err(NtripClientError::from)
+//!     });
+//!
+//! let handle = client.mount("VALDM").await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::future::Future;
+
+use futures::future::BoxFuture;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{config::NtripConfig, dial, NtripClientError};
+
+/// Bidirectional byte stream to the caster
+pub trait NtripStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> NtripStream for T {}
+
+/// Opens the connections to the caster,
+/// see the [module](crate::connector) documentation
+pub trait Connector: Send + Sync {
+    /// Opens a connection to the caster of this [NtripConfig]
+    fn connect<'a>(
+        &'a self,
+        config: &'a NtripConfig,
+    ) -> BoxFuture<'a, Result<Box<dyn NtripStream>, NtripClientError>>;
+}
+
+/// TCP connection, through the proxy of the [NtripConfig] if any.
+/// Every address of the host is tried, IPv6 and IPv4 attempts racing
+/// each other (RFC 8305), from the configured local address or interface.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct TcpConnector;
+
+impl Connector for TcpConnector {
+    fn connect<'a>(
+        &'a self,
+        config: &'a NtripConfig,
+    ) -> BoxFuture<'a, Result<Box<dyn NtripStream>, NtripClientError>> {
+        Box::pin(async move {
+            let sock = match config.effective_proxy() {
+                Some(proxy) => proxy.connect(config).await?,
+                None => dial::connect(&config.host, config.port, config).await?,
+            };
+
+            Ok(Box::new(sock) as Box<dyn NtripStream>)
+        })
+    }
+}
+
+impl<F, Fut, S> Connector for F
+where
+    F: Fn(&NtripConfig) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<S, NtripClientError>> + Send + 'static,
+    S: NtripStream + 'static,
+{
+    fn connect<'a>(
+        &'a self,
+        config: &'a NtripConfig,
+    ) -> BoxFuture<'a, Result<Box<dyn NtripStream>, NtripClientError>> {
+        let connect = self(config);
+        Box::pin(async move { Ok(Box::new(connect.await?) as Box<dyn NtripStream>) })
+    }
+}
@@ -0,0 +1,371 @@
+//! Credential sources
+//!
+//! A [CredentialProvider] is asked for [NtripCredentials] on every connection,
+//! so credentials can live outside of the configuration (environment, files,
+//! `.netrc`) and short-lived tokens can be rotated without rebuilding the client.
+//! Providers are asynchronous: files are read with [tokio::fs] and a token
+//! can be refreshed over the network without blocking the runtime.
+
+use std::{
+    env, fmt,
+    future::Future,
+    path::{Path, PathBuf},
+};
+
+use futures::future::BoxFuture;
+
+use crate::{
+    config::{NtripConfig, NtripCredentials},
+    NtripClientError,
+};
+
+/// Placeholder printed instead of secrets
+pub(crate) const REDACTED: &str = "***";
+
+/// Source of [NtripCredentials], queried before each connection
+///
+/// Async closures are providers too:
+/// ```
+/// use ntrip_client::{CredentialProvider, NtripConfig, NtripCredentials};
+///
+/// # async fn run() {
+/// let provider =
+///     |_: &NtripConfig| async { Ok(NtripCredentials::default().with_token("rotated")) };
+///
+/// let creds = provider.credentials(&NtripConfig::default()).await.unwrap();
+/// assert_eq!(creds.token.as_deref(), Some("rotated"));
+/// # }
+/// ```
+pub trait CredentialProvider: Send + Sync {
+    /// Returns the credentials to use with this server
+    fn credentials<'a>(
+        &'a self,
+        config: &'a NtripConfig,
+    ) -> BoxFuture<'a, Result<NtripCredentials, NtripClientError>>;
+}
+
+/// Static credentials
+impl CredentialProvider for NtripCredentials {
+    fn credentials<'a>(
+        &'a self,
+        _: &'a NtripConfig,
+    ) -> BoxFuture<'a, Result<NtripCredentials, NtripClientError>> {
+        Box::pin(async move { Ok(self.clone()) })
+    }
+}
+
+impl<F, Fut> CredentialProvider for F
+where
+    F: Fn(&NtripConfig) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<NtripCredentials, NtripClientError>> + Send + 'static,
+{
+    fn credentials<'a>(
+        &'a self,
+        config: &'a NtripConfig,
+    ) -> BoxFuture<'a, Result<NtripCredentials, NtripClientError>> {
+        Box::pin(self(config))
+    }
+}
+
+/// Credentials read from environment variables, when connecting
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct EnvCredentials {
+    /// Username variable
+    pub user_var: String,
+    /// Password variable
+    pub pass_var: String,
+    /// Bearer token variable
+    pub token_var: String,
+}
+
+impl Default for EnvCredentials {
+    /// Reads `NTRIP_USER`, `NTRIP_PASS` and `NTRIP_TOKEN`
+    fn default() -> Self {
+        Self {
+            user_var: "NTRIP_USER".to_string(),
+            pass_var: "NTRIP_PASS".to_string(),
+            token_var: "NTRIP_TOKEN".to_string(),
+        }
+    }
+}
+
+impl EnvCredentials {
+    /// Builds [EnvCredentials] reading these variables
+    pub fn new(user: &str, pass: &str, token: &str) -> Self {
+        Self {
+            user_var: user.to_string(),
+            pass_var: pass.to_string(),
+            token_var: token.to_string(),
+        }
+    }
+}
+
+impl CredentialProvider for EnvCredentials {
+    fn credentials<'a>(
+        &'a self,
+        _: &'a NtripConfig,
+    ) -> BoxFuture<'a, Result<NtripCredentials, NtripClientError>> {
+        Box::pin(async move {
+            Ok(NtripCredentials {
+                user: env::var(&self.user_var).unwrap_or_default(),
+                pass: env::var(&self.pass_var).unwrap_or_default(),
+                token: env::var(&self.token_var).ok(),
+            })
+        })
+    }
+}
+
+/// Credentials read from a file when connecting, so it can be updated
+/// (e.g. by a token refresh job) while the client runs.
+///
+/// The file holds `user=...`, `pass=...` and/or `token=...` lines,
+/// blank lines and `#` comments are ignored.
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FileCredentials {
+    /// Credentials file
+    pub path: PathBuf,
+}
+
+impl FileCredentials {
+    /// Builds [FileCredentials] reading this file
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn parse(content: &str) -> Result<NtripCredentials, NtripClientError> {
+        let mut creds = NtripCredentials::default();
+
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match line.split_once('=').map(|(k, v)| (k.trim(), v.trim())) {
+                Some(("user", user)) => creds.user = user.to_string(),
+                Some(("pass", pass)) => creds.pass = pass.to_string(),
+                Some(("token", token)) => creds.token = Some(token.to_string()),
+                Some((key, _)) => {
+                    return Err(NtripClientError::Credentials(format!(
+                        "unknown key \"{}\"",
+                        key
+                    )))
+                },
+                None => {
+                    return Err(NtripClientError::Credentials(
+                        "expected key=value lines".into(),
+                    ))
+                },
+            }
+        }
+
+        Ok(creds)
+    }
+}
+
+impl CredentialProvider for FileCredentials {
+    fn credentials<'a>(
+        &'a self,
+        _: &'a NtripConfig,
+    ) -> BoxFuture<'a, Result<NtripCredentials, NtripClientError>> {
+        Box::pin(async move { Self::parse(&tokio::fs::read_to_string(&self.path).await?) })
+    }
+}
+
+/// Per-host credentials from a `.netrc` file (`machine`, `login`, `password`
+/// and `default` entries), matched against [NtripConfig::host]
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NetrcCredentials {
+    /// `.netrc` file
+    pub path: PathBuf,
+}
+
+impl NetrcCredentials {
+    /// Builds [NetrcCredentials] reading this file
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Builds [NetrcCredentials] reading `$NETRC`, or `~/.netrc`
+    pub fn from_home() -> Result<Self, NtripClientError> {
+        if let Some(path) = env::var_os("NETRC") {
+            return Ok(Self::new(path));
+        }
+
+        let home = env::var_os("HOME")
+            .or_else(|| env::var_os("USERPROFILE"))
+            .ok_or_else(|| NtripClientError::Credentials("no home directory".into()))?;
+
+        Ok(Self::new(Path::new(&home).join(".netrc")))
+    }
+
+    /// Looks the host up, falling back to the `default` entry
+    fn lookup(content: &str, host: &str) -> Option<NtripCredentials> {
+        let mut tokens = content.split_whitespace();
+
+        let mut default = None;
+        // Current entry: (is this host, is default, credentials)
+        let mut entry: Option<(bool, bool, NtripCredentials)> = None;
+
+        let mut close = |entry: Option<(bool, bool, NtripCredentials)>| match entry {
+            Some((true, _, creds)) => Some(creds),
+            Some((false, true, creds)) => {
+                default = Some(creds);
+                None
+            },
+            _ => None,
+        };
+
+        while let Some(token) = tokens.next() {
+            match token {
+                "machine" => {
+                    if let Some(creds) = close(entry.take()) {
+                        return Some(creds);
+                    }
+                    let machine = tokens.next().unwrap_or_default();
+                    entry = Some((
+                        machine.eq_ignore_ascii_case(host),
+                        false,
+                        NtripCredentials::default(),
+                    ));
+                },
+                "default" => {
+                    if let Some(creds) = close(entry.take()) {
+                        return Some(creds);
+                    }
+                    entry = Some((false, true, NtripCredentials::default()));
+                },
+                "login" | "password" | "account" => {
+                    let value = tokens.next().unwrap_or_default();
+                    if let Some((_, _, creds)) = entry.as_mut() {
+                        match token {
+                            "login" => creds.user = value.to_string(),
+                            "password" => creds.pass = value.to_string(),
+                            _ => {},
+                        }
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        close(entry).or(default)
+    }
+}
+
+impl CredentialProvider for NetrcCredentials {
+    fn credentials<'a>(
+        &'a self,
+        config: &'a NtripConfig,
+    ) -> BoxFuture<'a, Result<NtripCredentials, NtripClientError>> {
+        Box::pin(async move {
+            let content = tokio::fs::read_to_string(&self.path).await?;
+            let host = config.host.trim_start_matches('[').trim_end_matches(']');
+
+            Ok(Self::lookup(&content, host).unwrap_or_default())
+        })
+    }
+}
+
+/// Debug output of a secret
+pub(crate) fn redact(secret: &str) -> &'static str {
+    if secret.is_empty() {
+        ""
+    } else {
+        REDACTED
+    }
+}
+
+impl fmt::Debug for NtripCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NtripCredentials")
+            .field("user", &self.user)
+            .field("pass", &redact(&self.pass))
+            .field("token", &self.token.as_ref().map(|_| REDACTED))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_netrc_lookup() {
+        let netrc = "
+            machine rtk2go.com login alice password secret1
+            default
+                login anonymous
+                password guest
+            machine caster.centipede.fr
+                login centipede
+                account ignored
+                password centipede
+        ";
+
+        let creds = NetrcCredentials::lookup(netrc, "RTK2GO.com").unwrap();
+        assert_eq!(
+            (creds.user.as_str(), creds.pass.as_str()),
+            ("alice", "secret1")
+        );
+
+        let creds = NetrcCredentials::lookup(netrc, "caster.centipede.fr").unwrap();
+        assert_eq!(creds.user, "centipede");
+        assert_eq!(creds.pass, "centipede");
+
+        let creds = NetrcCredentials::lookup(netrc, "unknown.org").unwrap();
+        assert_eq!(creds.user, "anonymous");
+
+        assert!(NetrcCredentials::lookup("machine a login b", "c").is_none());
+    }
+
+    #[test]
+    fn test_file_credentials() {
+        let creds =
+            FileCredentials::parse("# rotated hourly\nuser = bob\n\ntoken=abc.def\n").unwrap();
+        assert_eq!(creds.user, "bob");
+        assert_eq!(creds.token.as_deref(), Some("abc.def"));
+
+        assert!(FileCredentials::parse("bob:pass").is_err());
+        assert!(FileCredentials::parse("login=bob").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_file_provider() {
+        let path = env::temp_dir().join(format!("ntrip-creds-{}", std::process::id()));
+        tokio::fs::write(&path, "user=bob\ntoken=first\n")
+            .await
+            .unwrap();
+
+        let provider = FileCredentials::new(&path);
+        let config = NtripConfig::default();
+        let creds = provider.credentials(&config).await.unwrap();
+        assert_eq!(creds.token.as_deref(), Some("first"));
+
+        // Rotated while in use
+        tokio::fs::write(&path, "user=bob\ntoken=second\n")
+            .await
+            .unwrap();
+        let creds = provider.credentials(&config).await.unwrap();
+        assert_eq!(creds.token.as_deref(), Some("second"));
+
+        tokio::fs::remove_file(&path).await.unwrap();
+        assert!(provider.credentials(&config).await.is_err());
+    }
+
+    #[test]
+    fn test_redaction() {
+        let creds = NtripCredentials::default()
+            .with_username("bob")
+            .with_password("hunter2")
+            .with_token("abc.def");
+
+        let debug = format!("{:?}", creds);
+        assert!(debug.contains("bob"));
+        assert!(!debug.contains("hunter2"));
+        assert!(!debug.contains("abc.def"));
+    }
+}
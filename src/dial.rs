@@ -0,0 +1,215 @@
+//! Outgoing TCP connections
+//!
+//! Host names resolve to every address of both families, tried the
+//! RFC 8305 ("Happy Eyeballs") way: alternating families, the next attempt
+//! starting when the previous one fails or after [ATTEMPT_DELAY], first
+//! established connection wins. Sockets are bound to the local address or
+//! network interface of the [NtripConfig], if any, TCP sockets tuned with
+//! its [SocketOptions](crate::socket::SocketOptions).
+
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use futures::{stream::FuturesUnordered, StreamExt};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::{
+    net::{lookup_host, TcpSocket, TcpStream, UdpSocket},
+    select,
+    time::sleep,
+};
+use tracing::debug;
+
+use crate::config::NtripConfig;
+
+/// Delay before racing the next address (RFC 8305 recommended value)
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Resolves `host` and connects to the first address answering
+pub(crate) async fn connect(host: &str, port: u16, config: &NtripConfig) -> io::Result<TcpStream> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addrs = lookup_host((host, port)).await?.collect::<Vec<_>>();
+
+    connect_addrs(addrs, config).await
+}
+
+/// Races connections to these addresses, see the [module](self) documentation
+pub(crate) async fn connect_addrs(
+    addrs: Vec<SocketAddr>,
+    config: &NtripConfig,
+) -> io::Result<TcpStream> {
+    // A local address restricts the family
+    let addrs = addrs
+        .into_iter()
+        .filter(|a| {
+            config
+                .local_address
+                .is_none_or(|l| l.is_ipv4() == a.is_ipv4())
+        })
+        .collect::<Vec<_>>();
+
+    let mut pending = interleave(addrs).into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut error = None;
+
+    loop {
+        if let Some(addr) = pending.next() {
+            debug!("Connecting to {}", addr);
+            attempts.push(async move { (addr, attempt(addr, config).await) });
+        }
+
+        if attempts.is_empty() {
+            return Err(error.unwrap_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "no address to connect to")
+            }));
+        }
+
+        select! {
+            Some((addr, result)) = attempts.next() => match result {
+                Ok(sock) => return Ok(sock),
+                Err(e) => {
+                    debug!("Connection to {} failed: {}", addr, e);
+                    error = Some(e);
+                },
+            },
+            _ = sleep(ATTEMPT_DELAY), if pending.len() > 0 => {},
+        }
+    }
+}
+
+/// Orders addresses alternating families, starting with the first one returned
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first().map(SocketAddr::is_ipv6) else {
+        return addrs;
+    };
+
+    let (mut preferred, mut other): (Vec<_>, Vec<_>) =
+        addrs.into_iter().partition(|a| a.is_ipv6() == first);
+    preferred.reverse();
+    other.reverse();
+
+    let mut ordered = Vec::with_capacity(preferred.len() + other.len());
+    while let Some(addr) = preferred.pop() {
+        ordered.push(addr);
+        ordered.extend(other.pop());
+    }
+    ordered.extend(other.into_iter().rev());
+
+    ordered
+}
+
+/// Single connection attempt
+async fn attempt(addr: SocketAddr, config: &NtripConfig) -> io::Result<TcpStream> {
+    let socket = bound_socket(addr, Type::STREAM, Protocol::TCP, config)?;
+    TcpSocket::from_std_stream(socket.into())
+        .connect(addr)
+        .await
+}
+
+/// Binds an UDP socket of the family of `peer`, from the local address of
+/// `local` (the control connection), to the interface of the [NtripConfig]
+pub(crate) fn bind_udp(
+    peer: SocketAddr,
+    local: IpAddr,
+    config: &NtripConfig,
+) -> io::Result<UdpSocket> {
+    let socket = bound_socket(peer, Type::DGRAM, Protocol::UDP, config)?;
+    socket.bind(&SocketAddr::new(local, 0).into())?;
+
+    UdpSocket::from_std(socket.into())
+}
+
+/// Creates a non blocking socket for `peer`, bound to the
+/// local address and interface of the [NtripConfig]
+fn bound_socket(
+    peer: SocketAddr,
+    ty: Type,
+    protocol: Protocol,
+    config: &NtripConfig,
+) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(peer), ty, Some(protocol))?;
+    socket.set_nonblocking(true)?;
+
+    if let Some(interface) = &config.interface {
+        bind_device(&socket, interface)?;
+    }
+
+    if ty == Type::STREAM {
+        config.socket.apply(&socket)?;
+
+        if let Some(local) = config.local_address {
+            socket.bind(&SocketAddr::new(local, 0).into())?;
+        }
+    }
+
+    Ok(socket)
+}
+
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+fn bind_device(socket: &Socket, interface: &str) -> io::Result<()> {
+    socket.bind_device(Some(interface.as_bytes()))
+}
+
+#[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+fn bind_device(_: &Socket, _: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "binding to a network interface is not supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn test_interleave() {
+        let v4 = |i: u8| SocketAddr::from(([192, 0, 2, i], 2101));
+        let v6 = |i: u16| SocketAddr::from(([0x2001, 0xdb8, 0, 0, 0, 0, 0, i], 2101));
+
+        assert_eq!(
+            interleave(vec![v6(1), v6(2), v6(3), v4(1), v4(2)]),
+            vec![v6(1), v4(1), v6(2), v4(2), v6(3)]
+        );
+        assert_eq!(
+            interleave(vec![v4(1), v4(2), v4(3), v6(1)]),
+            vec![v4(1), v6(1), v4(2), v4(3)]
+        );
+        assert_eq!(interleave(vec![]), vec![]);
+    }
+
+    #[tokio::test]
+    async fn test_connect_addrs() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Unreachable (or black holed) first: the next address is raced
+        let start = Instant::now();
+        let addrs = vec![SocketAddr::from(([192, 0, 2, 1], addr.port())), addr];
+        let sock = connect_addrs(addrs, &NtripConfig::default()).await.unwrap();
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        // Bound to a local address, IPv6 candidates are skipped
+        let config = NtripConfig::default().with_local_address([127, 0, 0, 1].into());
+        let addrs = vec![
+            SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], addr.port())),
+            addr,
+        ];
+        let sock = connect_addrs(addrs, &config).await.unwrap();
+        assert_eq!(
+            sock.local_addr().unwrap().ip(),
+            config.local_address.unwrap()
+        );
+
+        // Nothing to connect to
+        let config = NtripConfig::default().with_local_address("::1".parse().unwrap());
+        assert!(connect_addrs(vec![addr], &config).await.is_err());
+    }
+}
@@ -0,0 +1,36 @@
+//! Timestamped messages
+//!
+//! [Envelope] wraps each decoded [Message] with its reception context:
+//! when it arrived, from which mount, and the raw frame it was decoded from.
+//! See [NtripHandle::into_envelopes](crate::NtripHandle::into_envelopes).
+
+use std::{
+    sync::Arc,
+    time::{Instant, SystemTime},
+};
+
+use rtcm_rs::Message;
+
+/// Decoded [Message] and its reception context
+#[derive(Debug)]
+pub struct Envelope {
+    /// Decoded message
+    pub message: Message,
+    /// Raw frame, preamble and CRC included
+    pub frame: Vec<u8>,
+    /// Mount point name
+    pub mount: Arc<str>,
+    /// Position of this message in the stream, starting at 0
+    pub sequence: u64,
+    /// Receive time (UTC)
+    pub received: SystemTime,
+    /// Receive instant, for interval measurements
+    pub instant: Instant,
+}
+
+impl Envelope {
+    /// Returns the frame length (bytes), preamble and CRC included
+    pub fn frame_len(&self) -> usize {
+        self.frame.len()
+    }
+}
@@ -19,7 +19,7 @@ pub enum NtripClientError {
     #[error("Header ToStrError error {0}")]
     ToStrError(#[from] ToStrError),
 
-    #[error("Response error")]
+    #[error("Response error: {0}")]
     ResponseError(String),
 
     #[error("Invalid URL")]
@@ -27,4 +27,28 @@ pub enum NtripClientError {
 
     #[error("Invalid port number")]
     InvalidPort,
+
+    #[error("TLS error: {0}")]
+    Tls(String),
+
+    #[error("Rustls error: {0}")]
+    Rustls(#[from] rustls::Error),
+
+    #[error("Proxy error: {0}")]
+    Proxy(String),
+
+    #[error("Credentials error: {0}")]
+    Credentials(String),
+
+    #[error("RTSP error: {0}")]
+    Rtsp(String),
+
+    #[error("Invalid message filter: {0}")]
+    InvalidFilter(String),
+
+    #[error("Invalid recording index entry: {0}")]
+    InvalidIndex(String),
+
+    #[error("Profile error: {0}")]
+    Profile(String),
 }
@@ -0,0 +1,168 @@
+//! RTCM message filtering
+//!
+//! [MessageFilter] selects RTCM message numbers. Mounted streams apply it
+//! to the frame header, before decoding, so unwanted messages cost nothing
+//! but framing.
+
+use std::{fmt, ops::RangeInclusive, str::FromStr};
+
+use crate::{framer::message_number, NtripClientError};
+
+/// Allow / deny list of RTCM message numbers.
+/// An empty allow list accepts every message that is not denied.
+///
+/// ```
+/// use ntrip_client::filter::MessageFilter;
+///
+/// // Station coordinates, all MSM messages but GLONASS, and GLONASS biases
+/// let filter = "1005,1006,1074-1127,1230,!1084-1087".parse::<MessageFilter>().unwrap();
+///
+/// assert!(filter.accepts(1006));
+/// assert!(filter.accepts(1077));
+/// assert!(!filter.accepts(1085));
+/// assert!(!filter.accepts(1019));
+/// ```
+#[derive(Clone, Default, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct MessageFilter {
+    /// Accepted message numbers, all when empty
+    pub allow: Vec<RangeInclusive<u16>>,
+    /// Rejected message numbers
+    pub deny: Vec<RangeInclusive<u16>>,
+}
+
+impl MessageFilter {
+    /// Copies and returns [MessageFilter] accepting these message numbers
+    pub fn with_allowed(&self, numbers: impl IntoIterator<Item = u16>) -> Self {
+        let mut s = self.clone();
+        s.allow.extend(numbers.into_iter().map(|n| n..=n));
+        s
+    }
+
+    /// Copies and returns [MessageFilter] accepting this range of message numbers
+    pub fn with_allowed_range(&self, range: RangeInclusive<u16>) -> Self {
+        let mut s = self.clone();
+        s.allow.push(range);
+        s
+    }
+
+    /// Copies and returns [MessageFilter] rejecting these message numbers
+    pub fn with_denied(&self, numbers: impl IntoIterator<Item = u16>) -> Self {
+        let mut s = self.clone();
+        s.deny.extend(numbers.into_iter().map(|n| n..=n));
+        s
+    }
+
+    /// Copies and returns [MessageFilter] rejecting this range of message numbers
+    pub fn with_denied_range(&self, range: RangeInclusive<u16>) -> Self {
+        let mut s = self.clone();
+        s.deny.push(range);
+        s
+    }
+
+    /// Returns true if this filter lets everything through
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Returns true if this message number passes the filter
+    pub fn accepts(&self, number: u16) -> bool {
+        (self.allow.is_empty() || self.allow.iter().any(|r| r.contains(&number)))
+            && !self.deny.iter().any(|r| r.contains(&number))
+    }
+
+    /// Returns true if this RTCM 3 frame passes the filter,
+    /// looking at the message number only
+    pub fn accepts_frame(&self, frame: &[u8]) -> bool {
+        self.is_empty() || message_number(frame).is_some_and(|n| self.accepts(n))
+    }
+}
+
+/// Formats [MessageFilter] as accepted by [FromStr], e.g. `1005,1074-1127,!1230`
+impl fmt::Display for MessageFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let allow = self.allow.iter().map(|r| (r, ""));
+        let deny = self.deny.iter().map(|r| (r, "!"));
+
+        for (i, (range, prefix)) in allow.chain(deny).enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+
+            if range.start() == range.end() {
+                write!(f, "{}{}", prefix, range.start())?;
+            } else {
+                write!(f, "{}{}-{}", prefix, range.start(), range.end())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses comma separated message numbers or ranges (`1074-1127`),
+/// denied ones being prefixed with `!`
+impl FromStr for MessageFilter {
+    type Err = NtripClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |item: &str| NtripClientError::InvalidFilter(item.to_string());
+
+        let mut filter = Self::default();
+
+        for item in s.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            let (deny, range) = match item.strip_prefix('!') {
+                Some(range) => (true, range.trim()),
+                None => (false, item),
+            };
+
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            let start = start.trim().parse::<u16>().map_err(|_| invalid(item))?;
+            let end = end.trim().parse::<u16>().map_err(|_| invalid(item))?;
+
+            if start > end {
+                return Err(invalid(item));
+            }
+
+            match deny {
+                true => filter.deny.push(start..=end),
+                false => filter.allow.push(start..=end),
+            }
+        }
+
+        Ok(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::station_frame;
+
+    #[test]
+    fn test_message_filter() {
+        let filter = MessageFilter::default();
+        assert!(filter.is_empty());
+        assert!(filter.accepts(1005));
+
+        let filter = filter
+            .with_allowed([1005, 1006])
+            .with_allowed_range(1074..=1127);
+        assert!(filter.accepts(1005) && filter.accepts(1127));
+        assert!(!filter.accepts(1033));
+        assert!(filter.accepts_frame(&station_frame(1)));
+
+        let filter = filter.with_denied([1005]);
+        assert!(!filter.accepts_frame(&station_frame(1)));
+        assert_eq!(filter.to_string(), "1005,1006,1074-1127,!1005");
+        assert_eq!(filter.to_string().parse::<MessageFilter>().unwrap(), filter);
+
+        // Deny only
+        let filter = "!1230".parse::<MessageFilter>().unwrap();
+        assert!(filter.accepts(1005) && !filter.accepts(1230));
+
+        assert!("1005-1004".parse::<MessageFilter>().is_err());
+        assert!("1005,msm".parse::<MessageFilter>().is_err());
+    }
+}
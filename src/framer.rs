@@ -0,0 +1,280 @@
+//! Incremental RTCM 3 framer
+//!
+//! [RtcmFramer] extracts RTCM 3 frames (`0xd3` preamble, 10 bit length,
+//! payload, CRC-24Q) from a byte stream delivered in arbitrary pieces.
+//! Candidate frames failing the length or CRC checks are dropped one byte
+//! at a time, so the framer resynchronizes on the next valid frame
+//! instead of losing the stream.
+
+use std::collections::VecDeque;
+
+/// RTCM 3 frame preamble
+pub const PREAMBLE: u8 = 0xd3;
+
+/// Header (preamble, reserved bits and length) size
+const HEADER_LEN: usize = 3;
+
+/// CRC-24Q size
+const CRC_LEN: usize = 3;
+
+/// Largest payload a 10 bit length allows
+pub const MAX_PAYLOAD_LEN: usize = 1023;
+
+/// CRC-24Q generator polynomial
+const CRC24Q_POLY: u32 = 0x1864cfb;
+
+const CRC24Q_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 16;
+        let mut bit = 0;
+        while bit < 8 {
+            crc <<= 1;
+            if crc & 0x1000000 != 0 {
+                crc ^= CRC24Q_POLY;
+            }
+            bit += 1;
+        }
+        table[i] = crc & 0xffffff;
+        i += 1;
+    }
+    table
+};
+
+/// Computes the CRC-24Q of these bytes
+pub fn crc24q(data: impl IntoIterator<Item = u8>) -> u32 {
+    data.into_iter().fold(0, |crc, b| {
+        ((crc << 8) & 0xffffff) ^ CRC24Q_TABLE[(((crc >> 16) as u8) ^ b) as usize]
+    })
+}
+
+/// Returns the message number of an RTCM 3 frame
+/// (first 12 bits of the payload)
+pub fn message_number(frame: &[u8]) -> Option<u16> {
+    match frame {
+        [PREAMBLE, _, _, hi, lo, ..] => Some(((*hi as u16) << 4) | (*lo as u16 >> 4)),
+        _ => None,
+    }
+}
+
+/// Reads `len` (up to 32) bits of an RTCM 3 frame payload, from this bit offset
+pub(crate) fn payload_bits(frame: &[u8], offset: usize, len: usize) -> Option<u32> {
+    let payload = frame.get(HEADER_LEN..)?;
+
+    (offset..offset + len).try_fold(0u32, |value, bit| {
+        let byte = payload.get(bit / 8)?;
+        Some((value << 1) | ((byte >> (7 - bit % 8)) & 1) as u32)
+    })
+}
+
+/// [RtcmFramer] counters
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct FramerStats {
+    /// Valid frames extracted
+    pub frames: u64,
+    /// Bytes of valid frames
+    pub frame_bytes: u64,
+    /// Candidate frames with an invalid CRC
+    pub crc_failures: u64,
+    /// Candidate frames with an invalid header (reserved bits set,
+    /// or a length contradicted by the following frame)
+    pub header_errors: u64,
+    /// Bytes dropped while searching for a valid frame
+    pub skipped_bytes: u64,
+}
+
+impl FramerStats {
+    /// Returns the number of bytes consumed so far
+    pub fn consumed_bytes(&self) -> u64 {
+        self.frame_bytes + self.skipped_bytes
+    }
+}
+
+/// Candidate frame check
+enum Candidate {
+    /// Valid frame of this length
+    Valid(usize),
+    /// Not enough data yet
+    Incomplete,
+    /// Reserved bits set
+    BadHeader,
+    /// CRC mismatch
+    BadCrc,
+}
+
+/// Incremental RTCM 3 framer, see the [module](crate::framer) documentation
+///
+/// ```
+/// use ntrip_client::framer::RtcmFramer;
+///
+/// let mut framer = RtcmFramer::default();
+///
+/// // Garbage, then an empty message (length 0) split across two reads
+/// framer.push(&[0x42, 0xd3, 0x00]);
+/// assert!(framer.next_frame().is_none());
+///
+/// framer.push(&[0x00, 0x47, 0xea, 0x4b]);
+/// assert_eq!(framer.next_frame(), Some(vec![0xd3, 0x00, 0x00, 0x47, 0xea, 0x4b]));
+/// assert_eq!(framer.stats().skipped_bytes, 1);
+/// ```
+#[derive(Clone, Default, Debug)]
+pub struct RtcmFramer {
+    buff: VecDeque<u8>,
+    stats: FramerStats,
+}
+
+impl RtcmFramer {
+    /// Appends received bytes
+    pub fn push(&mut self, data: &[u8]) {
+        self.buff.extend(data);
+    }
+
+    /// Returns the next valid frame (preamble and CRC included),
+    /// or None until more data is pushed
+    pub fn next_frame(&mut self) -> Option<Vec<u8>> {
+        loop {
+            // Drop everything up to the next preamble
+            let start = self
+                .buff
+                .iter()
+                .position(|b| *b == PREAMBLE)
+                .unwrap_or(self.buff.len());
+
+            if start > 0 {
+                self.skip(start);
+            }
+
+            match self.candidate(0) {
+                Candidate::Valid(frame_len) => {
+                    self.stats.frames += 1;
+                    self.stats.frame_bytes += frame_len as u64;
+
+                    return Some(self.buff.drain(..frame_len).collect());
+                },
+                Candidate::BadHeader => {
+                    self.stats.header_errors += 1;
+                    self.skip(1);
+                },
+                Candidate::BadCrc => {
+                    self.stats.crc_failures += 1;
+                    self.skip(1);
+                },
+                Candidate::Incomplete => {
+                    // A false preamble may announce a long frame: a complete
+                    // valid frame further on proves it wrong
+                    let next = (1..self.buff.len()).find(|i| {
+                        self.buff[*i] == PREAMBLE
+                            && matches!(self.candidate(*i), Candidate::Valid(_))
+                    })?;
+
+                    self.stats.header_errors += 1;
+                    self.skip(next);
+                },
+            }
+        }
+    }
+
+    /// Checks the candidate frame starting at this offset
+    fn candidate(&self, start: usize) -> Candidate {
+        let Some(header) = self.buff.range(start..).take(HEADER_LEN).nth(2) else {
+            return Candidate::Incomplete;
+        };
+
+        // 6 reserved bits, then the 10 bit payload length
+        let length = self.buff[start + 1];
+        if length & 0xfc != 0 {
+            return Candidate::BadHeader;
+        }
+
+        let payload_len = (((length & 0x03) as usize) << 8) | *header as usize;
+        let frame_len = HEADER_LEN + payload_len + CRC_LEN;
+        let end = start + frame_len;
+
+        if self.buff.len() < end {
+            return Candidate::Incomplete;
+        }
+
+        let crc = crc24q(self.buff.range(start..end - CRC_LEN).copied());
+        let expected = self
+            .buff
+            .range(end - CRC_LEN..end)
+            .fold(0u32, |crc, b| (crc << 8) | *b as u32);
+
+        if crc == expected {
+            Candidate::Valid(frame_len)
+        } else {
+            Candidate::BadCrc
+        }
+    }
+
+    fn skip(&mut self, len: usize) {
+        self.stats.skipped_bytes += len as u64;
+        self.buff.drain(..len);
+    }
+
+    /// Returns the number of buffered bytes, not framed yet
+    pub fn buffered(&self) -> usize {
+        self.buff.len()
+    }
+
+    /// Returns the counters
+    pub fn stats(&self) -> FramerStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::station_frame as frame;
+
+    #[test]
+    fn test_crc24q() {
+        // Empty message: preamble, zero length
+        assert_eq!(crc24q([0xd3, 0x00, 0x00]), 0x47ea4b);
+
+        let f = frame(7);
+        let len = f.len();
+        let crc = crc24q(f[..len - 3].iter().copied());
+        assert_eq!(crc.to_be_bytes()[1..], f[len - 3..]);
+        assert_eq!(message_number(&f), Some(1005));
+    }
+
+    #[test]
+    fn test_framer_resync() {
+        let mut framer = RtcmFramer::default();
+
+        // Corrupted frame, false preamble, valid frame split across pushes
+        let mut corrupted = frame(1);
+        corrupted[8] ^= 0xff;
+
+        let mut data = corrupted.clone();
+        data.extend([0xd3, 0x00, 0x42]);
+        data.extend(frame(2));
+        data.extend([0xd3, 0xff]);
+        data.extend(frame(3));
+
+        let (head, tail) = data.split_at(data.len() - 5);
+        framer.push(head);
+
+        assert_eq!(framer.next_frame(), Some(frame(2)));
+        assert_eq!(framer.next_frame(), None);
+        assert_eq!(
+            framer.buffered(),
+            data.len() - 5 - framer.stats().consumed_bytes() as usize
+        );
+
+        framer.push(tail);
+        assert_eq!(framer.next_frame(), Some(frame(3)));
+        assert_eq!(framer.next_frame(), None);
+        assert_eq!(framer.buffered(), 0);
+
+        let stats = framer.stats();
+        assert_eq!(stats.frames, 2);
+        assert!(stats.crc_failures >= 1);
+        assert!(stats.header_errors >= 2);
+        assert_eq!(stats.skipped_bytes as usize, corrupted.len() + 3 + 2);
+        assert_eq!(stats.consumed_bytes() as usize, data.len());
+    }
+}
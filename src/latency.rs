@@ -0,0 +1,277 @@
+//! Correction latency
+//!
+//! Observation messages (MSM 1071-1137 and legacy 1001-1004, 1009-1012) carry
+//! the GNSS epoch they describe. [epoch_time] reads it from the frame header,
+//! and [EpochTime::age_ms] compares it to the local receive time, taking the
+//! time scale offsets (GPS-UTC leap seconds, BeiDou, GLONASS) into account.
+//! [LatencyStats] summarizes these ages per mount and per message number.
+//!
+//! Latency measurements are only as good as the local clock: keep it
+//! synchronized (NTP, or better).
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::framer::{message_number, payload_bits};
+
+/// GPS epoch (1980-01-06), in seconds since the Unix epoch
+const GPS_EPOCH_UNIX_S: i64 = 315_964_800;
+
+/// GPS-UTC offset (s), effective from these Unix times (s)
+const LEAP_SECONDS: [(i64, i64); 18] = [
+    (362_793_600, 1),    // 1981-07-01
+    (394_329_600, 2),    // 1982-07-01
+    (425_865_600, 3),    // 1983-07-01
+    (489_024_000, 4),    // 1985-07-01
+    (567_993_600, 5),    // 1988-01-01
+    (631_152_000, 6),    // 1990-01-01
+    (662_688_000, 7),    // 1991-01-01
+    (709_948_800, 8),    // 1992-07-01
+    (741_484_800, 9),    // 1993-07-01
+    (773_020_800, 10),   // 1994-07-01
+    (820_454_400, 11),   // 1996-01-01
+    (867_715_200, 12),   // 1997-07-01
+    (915_148_800, 13),   // 1999-01-01
+    (1_136_073_600, 14), // 2006-01-01
+    (1_230_768_000, 15), // 2009-01-01
+    (1_341_100_800, 16), // 2012-07-01
+    (1_435_708_800, 17), // 2015-07-01
+    (1_483_228_800, 18), // 2017-01-01
+];
+
+/// BeiDou time is behind GPS time by this much (ms)
+const BDT_OFFSET_MS: i64 = 14_000;
+
+/// GLONASS time is UTC(SU) + 3h (ms)
+const GLONASST_OFFSET_MS: i64 = 3 * 3_600_000;
+
+const WEEK_MS: i64 = 604_800_000;
+const DAY_MS: i64 = 86_400_000;
+
+/// Returns the GPS-UTC offset (s) at this Unix time (s)
+pub fn leap_seconds(unix_s: i64) -> i64 {
+    LEAP_SECONDS
+        .iter()
+        .rev()
+        .find(|(since, _)| unix_s >= *since)
+        .map(|(_, leap)| *leap)
+        .unwrap_or_default()
+}
+
+/// Time scales of the epoch times
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum TimeScale {
+    /// GPS time, also used by SBAS MSM
+    Gpst,
+    /// Galileo system time, aligned to GPS time
+    Gst,
+    /// BeiDou time, 14 s behind GPS time
+    Bdt,
+    /// QZSS time, aligned to GPS time
+    Qzsst,
+    /// NavIC time, aligned to GPS time
+    Irnsst,
+}
+
+/// GNSS epoch time of an observation message
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum EpochTime {
+    /// Time of week (ms)
+    TimeOfWeek(TimeScale, u32),
+    /// GLONASS time of day (ms)
+    GlonassTimeOfDay(u32),
+}
+
+impl EpochTime {
+    /// Returns the age (ms) of this epoch when received at this instant.
+    /// Negative ages mean the local clock is behind.
+    pub fn age_ms(&self, received: SystemTime) -> i64 {
+        let unix_ms = match received.duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_millis() as i64,
+            Err(e) => -(e.duration().as_millis() as i64),
+        };
+
+        let (now, epoch, period) = match self {
+            Self::TimeOfWeek(scale, tow) => {
+                let leap = leap_seconds(unix_ms.div_euclid(1000));
+                let gps_ms = unix_ms - GPS_EPOCH_UNIX_S * 1000 + leap * 1000;
+
+                let now = match scale {
+                    TimeScale::Bdt => gps_ms - BDT_OFFSET_MS,
+                    _ => gps_ms,
+                };
+
+                (now, *tow as i64, WEEK_MS)
+            },
+            Self::GlonassTimeOfDay(tod) => (unix_ms + GLONASST_OFFSET_MS, *tod as i64, DAY_MS),
+        };
+
+        // Closest epoch, the message may be from the previous week / day
+        let age = (now - epoch).rem_euclid(period);
+        if age > period / 2 {
+            age - period
+        } else {
+            age
+        }
+    }
+}
+
+/// Returns the epoch time of an RTCM 3 observation frame,
+/// None for other messages
+pub fn epoch_time(frame: &[u8]) -> Option<EpochTime> {
+    let number = message_number(frame)?;
+
+    // Message number and station ID come first, 12 bits each
+    let tow = |scale| payload_bits(frame, 24, 30).map(|t| EpochTime::TimeOfWeek(scale, t));
+
+    match number {
+        1001..=1004 | 1071..=1077 | 1101..=1107 => tow(TimeScale::Gpst),
+        1091..=1097 => tow(TimeScale::Gst),
+        1111..=1117 => tow(TimeScale::Qzsst),
+        1121..=1127 => tow(TimeScale::Bdt),
+        1131..=1137 => tow(TimeScale::Irnsst),
+        1009..=1012 => payload_bits(frame, 24, 27).map(EpochTime::GlonassTimeOfDay),
+        // 3 bit day of week first
+        1081..=1087 => payload_bits(frame, 27, 27).map(EpochTime::GlonassTimeOfDay),
+        _ => None,
+    }
+}
+
+/// Latency summary
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Latency {
+    /// Measurements
+    pub count: u64,
+    /// Epochs dated in the future (local clock behind), not accounted for below
+    pub early: u64,
+    /// Latest latency
+    pub last: Duration,
+    /// Lowest latency
+    pub min: Duration,
+    /// Highest latency
+    pub max: Duration,
+    total: Duration,
+}
+
+impl Latency {
+    /// Accounts for this age (ms), see [EpochTime::age_ms]
+    pub fn record(&mut self, age_ms: i64) {
+        if age_ms < 0 {
+            self.early += 1;
+            return;
+        }
+
+        let latency = Duration::from_millis(age_ms as u64);
+
+        self.min = match self.count {
+            0 => latency,
+            _ => self.min.min(latency),
+        };
+        self.max = self.max.max(latency);
+        self.last = latency;
+        self.total += latency;
+        self.count += 1;
+    }
+
+    /// Returns the mean latency, None until measured
+    pub fn mean(&self) -> Option<Duration> {
+        (self.count > 0).then(|| self.total.div_f64(self.count as f64))
+    }
+}
+
+/// Latency statistics of a mount, see the [module](crate::latency) documentation
+#[derive(Clone, Default, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LatencyStats {
+    /// All observation messages
+    pub overall: Latency,
+    /// Per message number
+    pub messages: BTreeMap<u16, Latency>,
+}
+
+impl LatencyStats {
+    /// Accounts for this frame received at this instant,
+    /// if it is an observation message. Returns its age (ms).
+    pub fn record(&mut self, frame: &[u8], received: SystemTime) -> Option<i64> {
+        let number = message_number(frame)?;
+        let age = epoch_time(frame)?.age_ms(received);
+
+        self.overall.record(age);
+        self.messages.entry(number).or_default().record(age);
+
+        Some(age)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rtcm_rs::{
+        msg::{Msg1004T, Msg1087T, Msg1127T},
+        Message, MessageBuilder,
+    };
+
+    fn frame(message: Message) -> Vec<u8> {
+        MessageBuilder::new()
+            .build_message(&message)
+            .unwrap()
+            .to_vec()
+    }
+
+    #[test]
+    fn test_epoch_latency() {
+        assert_eq!(leap_seconds(0), 0);
+        assert_eq!(leap_seconds(1_483_228_800), 18);
+
+        // 2024-01-03 (Wednesday) 12:00:00.000 UTC: GPS TOW 3 days, 12h, 18s
+        let utc = UNIX_EPOCH + Duration::from_secs(1_704_283_200);
+        let tow = (3 * 86_400 + 12 * 3_600 + 18) * 1000;
+
+        let gps = frame(Message::Msg1004(Msg1004T {
+            gps_epoch_time_ms: tow - 1_500,
+            ..Default::default()
+        }));
+        let epoch = epoch_time(&gps).unwrap();
+        assert_eq!(epoch, EpochTime::TimeOfWeek(TimeScale::Gpst, tow - 1_500));
+        assert_eq!(epoch.age_ms(utc), 1_500);
+
+        let bds = frame(Message::Msg1127(Msg1127T {
+            bds_epoch_time_ms: tow - 14_000 - 800,
+            ..Default::default()
+        }));
+        assert_eq!(epoch_time(&bds).unwrap().age_ms(utc), 800);
+
+        // GLONASS: 15:00 Moscow time
+        let glo = frame(Message::Msg1087(Msg1087T {
+            glo_day_of_week: Some(3),
+            glo_epoch_time_ms: 15 * 3_600_000 - 250,
+            ..Default::default()
+        }));
+        assert_eq!(
+            epoch_time(&glo),
+            Some(EpochTime::GlonassTimeOfDay(15 * 3_600_000 - 250))
+        );
+        assert_eq!(epoch_time(&glo).unwrap().age_ms(utc), 250);
+
+        // Local clock behind
+        let ahead = EpochTime::TimeOfWeek(TimeScale::Gpst, tow + 100);
+        assert_eq!(ahead.age_ms(utc), -100);
+
+        let mut stats = LatencyStats::default();
+        stats.record(&gps, utc);
+        stats.record(&glo, utc);
+        stats.record(&gps, utc + Duration::from_millis(500));
+        assert_eq!(stats.record(&crate::mock::station_frame(1), utc), None);
+
+        assert_eq!(stats.overall.count, 3);
+        assert_eq!(stats.overall.min, Duration::from_millis(250));
+        assert_eq!(stats.overall.max, Duration::from_millis(2_000));
+        assert_eq!(
+            stats.messages[&1004].mean(),
+            Some(Duration::from_millis(1_750))
+        );
+    }
+}
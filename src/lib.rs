@@ -5,11 +5,82 @@
 pub mod config;
 pub use config::*;
 
+pub mod proxy;
+pub use proxy::*;
+
+pub mod tls;
+pub use tls::*;
+
+pub mod socket;
+pub use socket::*;
+
+pub mod url;
+pub use url::*;
+
+pub mod auth;
+pub use auth::*;
+
+pub mod credentials;
+pub use credentials::*;
+
+pub mod connector;
+pub use connector::*;
+
+pub mod framer;
+pub use framer::*;
+
+pub mod filter;
+pub use filter::*;
+
+pub mod stats;
+pub use stats::*;
+
+pub mod station;
+pub use station::*;
+
+pub mod latency;
+pub use latency::*;
+
+pub mod envelope;
+pub use envelope::*;
+
+pub mod multiplex;
+pub use multiplex::*;
+
+pub mod redundant;
+pub use redundant::*;
+
+pub mod blocking;
+
 pub mod snip;
 pub use snip::*;
 
+pub mod rtsp;
+pub use rtsp::*;
+
+pub mod recorder;
+pub use recorder::*;
+
+pub mod replay;
+pub use replay::*;
+
+#[cfg(feature = "profiles")]
+pub mod profile;
+#[cfg(feature = "profiles")]
+pub use profile::*;
+
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+
 mod error;
 pub use error::NtripClientError;
 
+mod protocol;
+
+mod dial;
+
 mod client;
-pub use client::NtripClient;
+pub use client::{CloseSummary, NtripClient, NtripEnvelopes, NtripHandle};
+
+/// Cancels mounts as a group, see [NtripClient::with_cancellation_token]
+pub use tokio_util::sync::CancellationToken;
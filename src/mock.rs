@@ -0,0 +1,576 @@
+//! In-process mock NTRIP caster, for offline testing
+//!
+//! [MockCaster] listens on an ephemeral local port and answers requests
+//! following a script, so every client path can be exercised without network access.
+//! It can also be reached in memory, see [MockCasterHandle::connector].
+//!
+//! ```
+//! # #[cfg(feature = "mock")]
+//! # async fn example() -> Result<(), anyhow::Error> {
+//! use futures::StreamExt;
+//! use ntrip_client::{
+//!     mock::{station_frame, MockCaster, MockMount},
+//!     NtripClient, NtripCredentials,
+//! };
+//!
+//! let frame = station_frame(42);
+//! let caster = MockCaster::default()
+//!     .with_mount("VALDM", MockMount::icy().with_data(&frame).then_stall())
+//!     .start()
+//!     .await?;
+//!
+//! let mut client = NtripClient::new(caster.config(), NtripCredentials::default()).await?;
+//! let mut handle = client.mount("VALDM").await?;
+//!
+//! let message = handle.next().await;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use base64::{engine::general_purpose, Engine as _};
+use percent_encoding::percent_decode_str;
+use rtcm_rs::{msg::Msg1005T, Message, MessageBuilder};
+use tokio::{
+    io::{duplex, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpListener,
+    task::JoinHandle,
+};
+use tracing::{debug, warn};
+
+use crate::{
+    auth::{digest_authorization, AuthChallenge, AuthScheme},
+    config::{NtripConfig, NtripCredentials},
+    connector::Connector,
+    snip::MountInfo,
+    NtripClientError,
+};
+
+const MOCK_NONCE: &str = "dcd98b7102dd2f0e8b11d0f600bfb0c093";
+const MOCK_OPAQUE: &str = "5ccc069c403ebaf9f0171e9517f40e41";
+
+/// How a [MockMount] answers the request
+#[derive(Clone, PartialEq, Debug)]
+pub enum MockResponse {
+    /// NTRIP 1.0 `ICY 200 OK`
+    Icy,
+    /// `HTTP/1.1 200 OK`, data sent as is
+    Http,
+    /// `HTTP/1.1 200 OK` with chunked transfer encoding
+    Chunked,
+    /// HTTP/1.1 error status (code, reason)
+    Status(u16, String),
+    /// Arbitrary bytes instead of a status line
+    Garbage(Vec<u8>),
+    /// No response head, the connection is kept open
+    Silent,
+}
+
+/// Scripted step of a [MockMount], played after the response head
+#[derive(Clone, PartialEq, Debug)]
+pub enum MockStep {
+    /// Send bytes (as one chunk for [MockResponse::Chunked])
+    Send(Vec<u8>),
+    /// Wait before the next step
+    Delay(Duration),
+    /// Keep the connection open without sending anything
+    Stall,
+    /// Close the connection cleanly (last chunk for [MockResponse::Chunked])
+    Close,
+    /// Drop the connection immediately
+    Abort,
+}
+
+/// Scripted mount point of a [MockCaster]
+#[derive(Clone, PartialEq, Debug)]
+pub struct MockMount {
+    /// Response to the mount request
+    pub response: MockResponse,
+    /// Steps played once the response head is sent
+    pub steps: Vec<MockStep>,
+}
+
+impl MockMount {
+    /// [MockMount] answering with [MockResponse]
+    pub fn new(response: MockResponse) -> Self {
+        Self {
+            response,
+            steps: Vec::new(),
+        }
+    }
+
+    /// [MockMount] answering `ICY 200 OK`
+    pub fn icy() -> Self {
+        Self::new(MockResponse::Icy)
+    }
+
+    /// [MockMount] answering `HTTP/1.1 200 OK`
+    pub fn http() -> Self {
+        Self::new(MockResponse::Http)
+    }
+
+    /// [MockMount] answering `HTTP/1.1 200 OK` with chunked data
+    pub fn chunked() -> Self {
+        Self::new(MockResponse::Chunked)
+    }
+
+    /// [MockMount] answering with an HTTP error status
+    pub fn status(code: u16, reason: &str) -> Self {
+        Self::new(MockResponse::Status(code, reason.to_string()))
+    }
+
+    /// [MockMount] answering with garbage
+    pub fn garbage(data: &[u8]) -> Self {
+        Self::new(MockResponse::Garbage(data.to_vec()))
+    }
+
+    /// [MockMount] never answering
+    pub fn silent() -> Self {
+        Self::new(MockResponse::Silent)
+    }
+
+    /// Copies and returns [MockMount] with an additional [MockStep]
+    pub fn with_step(&self, step: MockStep) -> Self {
+        let mut s = self.clone();
+        s.steps.push(step);
+        s
+    }
+
+    /// Copies and returns [MockMount] sending `data`
+    pub fn with_data(&self, data: &[u8]) -> Self {
+        self.with_step(MockStep::Send(data.to_vec()))
+    }
+
+    /// Copies and returns [MockMount] waiting `delay`
+    pub fn with_delay(&self, delay: Duration) -> Self {
+        self.with_step(MockStep::Delay(delay))
+    }
+
+    /// Copies and returns [MockMount] stalling once the script is played
+    pub fn then_stall(&self) -> Self {
+        self.with_step(MockStep::Stall)
+    }
+
+    /// Copies and returns [MockMount] closing once the script is played
+    pub fn then_close(&self) -> Self {
+        self.with_step(MockStep::Close)
+    }
+
+    /// Copies and returns [MockMount] dropping the connection once the script is played
+    pub fn then_abort(&self) -> Self {
+        self.with_step(MockStep::Abort)
+    }
+}
+
+/// Request received by a [MockCaster]
+#[derive(Clone, PartialEq, Debug)]
+pub struct MockRequest {
+    /// Request line, e.g. `GET /VALDM HTTP/1.0`
+    pub request_line: String,
+    /// Request path, e.g. `/VALDM`
+    pub path: String,
+    /// Header (name, value) pairs in reception order
+    pub headers: Vec<(String, String)>,
+}
+
+impl MockRequest {
+    /// Returns the value of a header (case insensitive name)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Mock NTRIP caster definition, see the [module](crate::mock) documentation
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct MockCaster {
+    /// Sourcetable served on `GET /`
+    pub sourcetable: Vec<MountInfo>,
+    /// Credentials required on every request, if any
+    pub credentials: Option<NtripCredentials>,
+    /// Scheme of the required credentials: [AuthScheme::Digest],
+    /// [AuthScheme::Bearer], or Basic otherwise
+    pub auth: AuthScheme,
+    /// Scripted mount points
+    pub mounts: HashMap<String, MockMount>,
+}
+
+impl MockCaster {
+    /// Copies and returns [MockCaster] serving an additional sourcetable entry
+    pub fn with_source(&self, info: &MountInfo) -> Self {
+        let mut s = self.clone();
+        s.sourcetable.push(info.clone());
+        s
+    }
+
+    /// Copies and returns [MockCaster] requiring these credentials, with the
+    /// [AuthScheme] set by [Self::with_auth_scheme] (Basic by default)
+    pub fn with_credentials(&self, creds: &NtripCredentials) -> Self {
+        let mut s = self.clone();
+        s.credentials = Some(creds.clone());
+        s
+    }
+
+    /// Copies and returns [MockCaster] requiring this [AuthScheme]
+    pub fn with_auth_scheme(&self, auth: AuthScheme) -> Self {
+        let mut s = self.clone();
+        s.auth = auth;
+        s
+    }
+
+    /// Copies and returns [MockCaster] with an additional [MockMount]
+    pub fn with_mount(&self, name: &str, mount: MockMount) -> Self {
+        let mut s = self.clone();
+        s.mounts.insert(name.to_string(), mount);
+        s
+    }
+
+    /// Starts serving on an ephemeral local port
+    pub async fn start(&self) -> Result<MockCasterHandle, NtripClientError> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        debug!("Mock caster listening on {}", addr);
+
+        let caster = Arc::new(self.clone());
+        let task_caster = caster.clone();
+        let task_requests = requests.clone();
+
+        let task = tokio::task::spawn(async move {
+            let mut connections = Vec::new();
+
+            while let Ok((sock, peer)) = listener.accept().await {
+                debug!("Mock caster connection from {}", peer);
+
+                let caster = task_caster.clone();
+                let requests = task_requests.clone();
+
+                connections.push(AbortOnDrop(tokio::task::spawn(async move {
+                    if let Err(e) = caster.serve(sock, &requests).await {
+                        warn!("Mock caster connection error: {}", e);
+                    }
+                })));
+            }
+        });
+
+        Ok(MockCasterHandle {
+            addr,
+            caster,
+            requests,
+            connections: Default::default(),
+            _task: AbortOnDrop(task),
+        })
+    }
+
+    fn authorized(&self, request: &MockRequest, creds: &NtripCredentials) -> bool {
+        let Some(authorization) = request.header("Authorization") else {
+            return false;
+        };
+
+        match self.auth {
+            AuthScheme::Digest => {
+                // Digest parameters share the challenge syntax
+                let Some(params) = AuthChallenge::parse_all(authorization).pop() else {
+                    return false;
+                };
+
+                let challenge = AuthChallenge {
+                    scheme: "Digest".to_string(),
+                    params: [
+                        ("realm", "mock"),
+                        ("qop", "auth"),
+                        ("nonce", MOCK_NONCE),
+                        ("opaque", MOCK_OPAQUE),
+                        ("algorithm", params.param("algorithm").unwrap_or("MD5")),
+                    ]
+                    .into_iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+                };
+
+                let expected = digest_authorization(
+                    &challenge,
+                    creds,
+                    "GET",
+                    &request.path,
+                    params.param("cnonce").unwrap_or_default(),
+                )
+                .and_then(|e| AuthChallenge::parse_all(&e).pop());
+
+                params.param("username") == Some(creds.user.as_str())
+                    && params.param("uri") == Some(request.path.as_str())
+                    && expected.is_some_and(|e| e.param("response") == params.param("response"))
+            },
+            AuthScheme::Bearer => {
+                creds
+                    .token
+                    .as_ref()
+                    .map(|t| format!("Bearer {}", t))
+                    .as_deref()
+                    == Some(authorization)
+            },
+            _ => {
+                let expected = format!(
+                    "Basic {}",
+                    general_purpose::STANDARD.encode(format!("{}:{}", creds.user, creds.pass))
+                );
+                authorization == expected
+            },
+        }
+    }
+
+    async fn serve(
+        &self,
+        mut sock: impl AsyncRead + AsyncWrite + Unpin,
+        requests: &Mutex<Vec<MockRequest>>,
+    ) -> Result<(), NtripClientError> {
+        let Some(request) = read_request(&mut sock).await? else {
+            return Ok(());
+        };
+
+        debug!("Mock caster request: {}", request.request_line);
+        requests.lock().unwrap().push(request.clone());
+
+        if let Some(creds) = &self.credentials {
+            if !self.authorized(&request, creds) {
+                let challenge = match self.auth {
+                    AuthScheme::Digest => format!(
+                        "Digest realm=\"mock\", qop=\"auth\", nonce=\"{}\", opaque=\"{}\"",
+                        MOCK_NONCE, MOCK_OPAQUE
+                    ),
+                    AuthScheme::Bearer => "Bearer realm=\"mock\"".to_string(),
+                    _ => "Basic realm=\"mock\"".to_string(),
+                };
+
+                sock.write_all(
+                    format!(
+                        "HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: {}\r\nContent-Length: 0\r\n\r\n",
+                        challenge
+                    )
+                    .as_bytes(),
+                )
+                .await?;
+                return Ok(());
+            }
+        }
+
+        if request.path == "/" {
+            return self.serve_sourcetable(sock).await;
+        }
+
+        let name = percent_decode_str(request.path.trim_start_matches('/')).decode_utf8_lossy();
+
+        let Some(mount) = self.mounts.get(name.as_ref()) else {
+            sock.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")
+                .await?;
+            return Ok(());
+        };
+
+        let chunked = match &mount.response {
+            MockResponse::Icy => {
+                sock.write_all(b"ICY 200 OK\r\n\r\n").await?;
+                false
+            },
+            MockResponse::Http => {
+                sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: gnss/data\r\n\r\n")
+                    .await?;
+                false
+            },
+            MockResponse::Chunked => {
+                sock.write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Type: gnss/data\r\nTransfer-Encoding: chunked\r\n\r\n",
+                )
+                .await?;
+                true
+            },
+            MockResponse::Status(code, reason) => {
+                sock.write_all(
+                    format!("HTTP/1.1 {} {}\r\nContent-Length: 0\r\n\r\n", code, reason).as_bytes(),
+                )
+                .await?;
+                return Ok(());
+            },
+            MockResponse::Garbage(data) => {
+                sock.write_all(data).await?;
+                return Ok(());
+            },
+            MockResponse::Silent => {
+                std::future::pending::<()>().await;
+                return Ok(());
+            },
+        };
+
+        for step in &mount.steps {
+            match step {
+                MockStep::Send(data) if chunked => {
+                    sock.write_all(format!("{:x}\r\n", data.len()).as_bytes())
+                        .await?;
+                    sock.write_all(data).await?;
+                    sock.write_all(b"\r\n").await?;
+                },
+                MockStep::Send(data) => {
+                    sock.write_all(data).await?;
+                },
+                MockStep::Delay(delay) => {
+                    sock.flush().await?;
+                    tokio::time::sleep(*delay).await;
+                },
+                MockStep::Stall => {
+                    sock.flush().await?;
+                    std::future::pending::<()>().await;
+                },
+                MockStep::Close => {
+                    if chunked {
+                        sock.write_all(b"0\r\n\r\n").await?;
+                    }
+                    sock.shutdown().await?;
+                    return Ok(());
+                },
+                MockStep::Abort => {
+                    return Ok(());
+                },
+            }
+        }
+
+        sock.shutdown().await?;
+        Ok(())
+    }
+
+    async fn serve_sourcetable(
+        &self,
+        mut sock: impl AsyncWrite + Unpin,
+    ) -> Result<(), NtripClientError> {
+        let mut body = String::new();
+        for info in &self.sourcetable {
+            body.push_str(&format!("{}\r\n", info));
+        }
+        body.push_str("ENDSOURCETABLE\r\n");
+
+        sock.write_all(
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: gnss/sourcetable\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .as_bytes(),
+        )
+        .await?;
+        sock.shutdown().await?;
+
+        Ok(())
+    }
+}
+
+/// Running [MockCaster], stops serving when dropped
+pub struct MockCasterHandle {
+    addr: SocketAddr,
+    caster: Arc<MockCaster>,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+    connections: Arc<Mutex<Vec<AbortOnDrop>>>,
+    _task: AbortOnDrop,
+}
+
+impl MockCasterHandle {
+    /// Local address the [MockCaster] listens on
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// [NtripConfig] to connect to this [MockCaster]
+    pub fn config(&self) -> NtripConfig {
+        NtripConfig::default()
+            .with_host(&self.addr.ip().to_string())
+            .with_port(self.addr.port())
+            .without_tls()
+    }
+
+    /// Requests received so far
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// In-memory [Connector] to this [MockCaster], bypassing the network
+    pub fn connector(&self) -> impl Connector {
+        let caster = self.caster.clone();
+        let requests = self.requests.clone();
+        let connections = self.connections.clone();
+
+        move |_: &NtripConfig| {
+            let (sock, server) = duplex(4096);
+            let (caster, requests) = (caster.clone(), requests.clone());
+
+            let task = tokio::task::spawn(async move {
+                if let Err(e) = caster.serve(server, &requests).await {
+                    warn!("Mock caster connection error: {}", e);
+                }
+            });
+            connections.lock().unwrap().push(AbortOnDrop(task));
+
+            async move { Ok::<_, NtripClientError>(sock) }
+        }
+    }
+}
+
+/// Encodes an RTCM 1005 (stationary reference station) frame, handy as test data
+pub fn station_frame(station_id: u16) -> Vec<u8> {
+    let mut builder = MessageBuilder::new();
+    builder
+        .build_message(&Message::Msg1005(Msg1005T {
+            reference_station_id: station_id,
+            ..Default::default()
+        }))
+        .expect("valid 1005 message")
+        .to_vec()
+}
+
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Reads a request head, returns None if the connection closed first
+pub(crate) async fn read_request(
+    sock: &mut (impl AsyncRead + Unpin),
+) -> Result<Option<MockRequest>, NtripClientError> {
+    let mut buff = Vec::with_capacity(1024);
+
+    while !buff.windows(4).any(|w| w == b"\r\n\r\n") {
+        if sock.read_buf(&mut buff).await? == 0 {
+            return Ok(None);
+        }
+    }
+
+    let head = String::from_utf8_lossy(&buff);
+    let mut lines = head.lines();
+
+    let request_line = lines.next().unwrap_or_default().to_string();
+    let path = request_line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or_default()
+        .to_string();
+
+    let headers = lines
+        .take_while(|l| !l.is_empty())
+        .filter_map(|l| l.split_once(':'))
+        .map(|(n, v)| (n.trim().to_string(), v.trim().to_string()))
+        .collect();
+
+    Ok(Some(MockRequest {
+        request_line,
+        path,
+        headers,
+    }))
+}
@@ -0,0 +1,494 @@
+//! Multi-mount multiplexer
+//!
+//! [NtripMultiplexer] mounts several sources, possibly on different casters,
+//! and merges them into a single [Stream] of [MuxEvent]s tagged by source name.
+//! Sources run independently: when one fails to mount or its stream ends,
+//! it is mounted again after a delay (doubled on each consecutive failure)
+//! while the others keep flowing. The delay is reset once a source delivers
+//! a message: a mount accepted then closed straight away keeps backing off.
+//! A caster not answering the mount request within the mount timeout
+//! counts as a failure.
+//! All sources are unmounted when the [MuxStream] is dropped.
+
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{Stream, StreamExt};
+use tokio::{
+    select,
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+    time::{sleep, timeout},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use crate::{
+    client::NtripClient,
+    config::{MountOptions, NtripConfig},
+    credentials::CredentialProvider,
+    envelope::Envelope,
+};
+
+/// A mount to multiplex
+#[derive(Clone)]
+pub struct MuxSource {
+    /// Name tagging the [MuxEvent]s of this source
+    pub name: Arc<str>,
+    /// Caster configuration
+    pub config: NtripConfig,
+    /// Mount point name
+    pub mount: String,
+    /// Mount options
+    pub options: MountOptions,
+    /// Retry delays of this source, the [NtripMultiplexer] ones when not set
+    pub retry_delay: Option<(Duration, Duration)>,
+    credentials: Arc<dyn CredentialProvider>,
+}
+
+impl MuxSource {
+    /// Builds a [MuxSource] mounting `mount` on the `config` caster
+    pub fn new(
+        name: &str,
+        config: NtripConfig,
+        credentials: impl CredentialProvider + 'static,
+        mount: &str,
+    ) -> Self {
+        Self {
+            name: Arc::from(name),
+            config,
+            mount: mount.to_string(),
+            options: MountOptions::default(),
+            retry_delay: None,
+            credentials: Arc::new(credentials),
+        }
+    }
+
+    /// Copies and returns [MuxSource] using these [MountOptions]
+    pub fn with_options(&self, options: MountOptions) -> Self {
+        let mut s = self.clone();
+        s.options = options;
+        s
+    }
+
+    /// Copies and returns [MuxSource] with its own retry delays,
+    /// see [NtripMultiplexer::with_retry_delay]
+    pub fn with_retry_delay(&self, initial: Duration, max: Duration) -> Self {
+        let mut s = self.clone();
+        s.retry_delay = Some((initial, max.max(initial)));
+        s
+    }
+}
+
+/// Item of the multiplexed [Stream]
+#[derive(Debug)]
+pub enum MuxEvent {
+    /// Source mounted
+    Connected { source: Arc<str> },
+    /// Message received from a source
+    Message {
+        source: Arc<str>,
+        envelope: Box<Envelope>,
+    },
+    /// Source failed to mount or its stream ended,
+    /// it will be mounted again after `retry_in`
+    Disconnected {
+        source: Arc<str>,
+        reason: String,
+        retry_in: Duration,
+    },
+}
+
+impl MuxEvent {
+    /// Returns the name of the source of this event
+    pub fn source(&self) -> &str {
+        match self {
+            Self::Connected { source }
+            | Self::Message { source, .. }
+            | Self::Disconnected { source, .. } => source,
+        }
+    }
+}
+
+/// Multiplexes several mounts, see the [module](crate::multiplex) documentation
+///
+/// ```no_run
+/// use futures::StreamExt;
+/// use ntrip_client::{MuxEvent, MuxSource, NtripConfig, NtripCredentials, NtripMultiplexer};
+///
+/// # async fn run() {
+/// let base = NtripConfig::default().with_host("caster.example.com");
+/// let ssr = NtripConfig::default().with_host("ssr.example.com").with_port(2101);
+/// let creds = NtripCredentials::default().with_username("user").with_password("pass");
+///
+/// let mut events = NtripMultiplexer::default()
+///     .with_source(MuxSource::new("base", base, creds.clone(), "BASE00FRA0"))
+///     .with_source(MuxSource::new("ssr", ssr, creds, "SSRA00CNE0"))
+///     .start();
+///
+/// while let Some(event) = events.next().await {
+///     if let MuxEvent::Message { source, envelope } = event {
+///         println!("{}: {:?}", source, envelope.message);
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct NtripMultiplexer {
+    sources: Vec<MuxSource>,
+    retry_delay: Duration,
+    max_retry_delay: Duration,
+    mount_timeout: Duration,
+    cancel: Option<CancellationToken>,
+}
+
+impl Default for NtripMultiplexer {
+    fn default() -> Self {
+        Self {
+            sources: Vec::new(),
+            retry_delay: Duration::from_secs(1),
+            max_retry_delay: Duration::from_secs(60),
+            mount_timeout: Duration::from_secs(30),
+            cancel: None,
+        }
+    }
+}
+
+impl NtripMultiplexer {
+    /// Copies and returns [NtripMultiplexer] with this additional [MuxSource]
+    pub fn with_source(&self, source: MuxSource) -> Self {
+        let mut s = self.clone();
+        s.sources.push(source);
+        s
+    }
+
+    /// Copies and returns [NtripMultiplexer] waiting `initial` before mounting
+    /// a failed source again, doubled on each consecutive failure up to `max`
+    pub fn with_retry_delay(&self, initial: Duration, max: Duration) -> Self {
+        let mut s = self.clone();
+        s.retry_delay = initial;
+        s.max_retry_delay = max.max(initial);
+        s
+    }
+
+    /// Copies and returns [NtripMultiplexer] giving up on a mount request
+    /// (connection and response head) after this long
+    pub fn with_mount_timeout(&self, timeout: Duration) -> Self {
+        let mut s = self.clone();
+        s.mount_timeout = timeout;
+        s
+    }
+
+    /// Copies and returns [NtripMultiplexer] unmounting all sources
+    /// when this [CancellationToken] is cancelled
+    pub fn with_cancellation_token(&self, token: CancellationToken) -> Self {
+        let mut s = self.clone();
+        s.cancel = Some(token);
+        s
+    }
+
+    /// Returns the sources
+    pub fn sources(&self) -> &[MuxSource] {
+        &self.sources
+    }
+
+    /// Mounts all sources. The [MuxStream] ends once closed or cancelled,
+    /// and unmounts the sources when dropped.
+    pub fn start(self) -> MuxStream {
+        let (tx, rx) = unbounded_channel();
+        let cancel = self
+            .cancel
+            .as_ref()
+            .map(CancellationToken::child_token)
+            .unwrap_or_default();
+
+        let tasks = self
+            .sources
+            .into_iter()
+            .map(|source| {
+                let (retry_delay, max_retry_delay) = source
+                    .retry_delay
+                    .unwrap_or((self.retry_delay, self.max_retry_delay));
+
+                tokio::task::spawn(run_source(
+                    source,
+                    retry_delay,
+                    max_retry_delay,
+                    self.mount_timeout,
+                    cancel.clone(),
+                    tx.clone(),
+                ))
+            })
+            .collect();
+
+        MuxStream {
+            _tasks: tasks,
+            cancel,
+            events_rx: rx,
+        }
+    }
+}
+
+/// Mounts this source until cancelled, restarting it on failure
+async fn run_source(
+    source: MuxSource,
+    retry_delay: Duration,
+    max_retry_delay: Duration,
+    mount_timeout: Duration,
+    cancel: CancellationToken,
+    tx: UnboundedSender<MuxEvent>,
+) {
+    let mut delay = retry_delay;
+
+    loop {
+        let credentials = source.credentials.clone();
+        let provider = move |config: &NtripConfig| {
+            let (credentials, config) = (credentials.clone(), config.clone());
+            async move { credentials.credentials(&config).await }
+        };
+
+        let mount = async {
+            NtripClient::with_credential_provider(source.config.clone(), provider)
+                .await?
+                .with_cancellation_token(cancel.clone())
+                .mount_with_options(&source.mount, source.options.clone())
+                .await
+        };
+
+        let mounted = select! {
+            mounted = timeout(mount_timeout, mount) => mounted,
+            _ = cancel.cancelled() => return,
+        };
+
+        let reason = match mounted {
+            Ok(Ok(handle)) => {
+                debug!("{}: mounted {}", source.name, source.mount);
+
+                let connected = MuxEvent::Connected {
+                    source: source.name.clone(),
+                };
+                if tx.send(connected).is_err() {
+                    return;
+                }
+
+                let mut envelopes = handle.into_envelopes();
+
+                loop {
+                    select! {
+                        envelope = envelopes.next() => match envelope {
+                            Some(envelope) => {
+                                delay = retry_delay;
+
+                                let event = MuxEvent::Message {
+                                    source: source.name.clone(),
+                                    envelope: Box::new(envelope),
+                                };
+                                if tx.send(event).is_err() {
+                                    return;
+                                }
+                            },
+                            None => break format!("{}: end of stream", source.mount),
+                        },
+                        _ = cancel.cancelled() => return,
+                    }
+                }
+            },
+            Ok(Err(e)) => format!("{}: {}", source.mount, e),
+            Err(_) => format!("{}: no response within {:?}", source.mount, mount_timeout),
+        };
+
+        warn!("{}: {}, mounting again in {:?}", source.name, reason, delay);
+
+        let disconnected = MuxEvent::Disconnected {
+            source: source.name.clone(),
+            reason,
+            retry_in: delay,
+        };
+        if tx.send(disconnected).is_err() {
+            return;
+        }
+
+        select! {
+            _ = sleep(delay) => {},
+            _ = cancel.cancelled() => return,
+            _ = tx.closed() => return,
+        }
+
+        delay = (delay * 2).min(max_retry_delay);
+    }
+}
+
+/// Merged [Stream] of [MuxEvent]s, see [NtripMultiplexer::start]
+pub struct MuxStream {
+    _tasks: Vec<JoinHandle<()>>,
+    cancel: CancellationToken,
+    events_rx: UnboundedReceiver<MuxEvent>,
+}
+
+impl MuxStream {
+    /// Unmounts all sources: the stream ends after the pending events
+    pub fn close(&self) {
+        self.cancel.cancel();
+    }
+}
+
+impl Drop for MuxStream {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+impl Stream for MuxStream {
+    type Item = MuxEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events_rx.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::{
+        config::NtripCredentials,
+        mock::{station_frame, MockCaster, MockMount},
+    };
+
+    #[tokio::test]
+    async fn test_multiplexer() {
+        // Two casters: the base closes after each frame and gets mounted again
+        let base = MockCaster::default()
+            .with_mount(
+                "BASE",
+                MockMount::icy().with_data(&station_frame(1)).then_close(),
+            )
+            .with_mount("EMPTY", MockMount::icy().then_close())
+            .start()
+            .await
+            .unwrap();
+
+        let ssr = MockCaster::default()
+            .with_mount(
+                "SSR",
+                MockMount::icy().with_data(&station_frame(2)).then_stall(),
+            )
+            .start()
+            .await
+            .unwrap();
+
+        let creds = NtripCredentials::default();
+
+        let mut events = NtripMultiplexer::default()
+            .with_source(MuxSource::new("base", base.config(), creds.clone(), "BASE"))
+            .with_source(MuxSource::new("ssr", ssr.config(), creds.clone(), "SSR"))
+            .with_source(MuxSource::new(
+                "missing",
+                ssr.config(),
+                creds.clone(),
+                "NONE",
+            ))
+            .with_source(MuxSource::new("empty", base.config(), creds, "EMPTY"))
+            .with_retry_delay(Duration::from_millis(10), Duration::from_millis(40))
+            .start();
+
+        let (mut base_messages, mut ssr_messages, mut missing_failures) = (0, 0, 0);
+        let mut empty_delays = Vec::new();
+
+        while base_messages < 2
+            || ssr_messages < 1
+            || missing_failures < 3
+            || empty_delays.len() < 3
+        {
+            let event = timeout(Duration::from_secs(5), events.next())
+                .await
+                .unwrap()
+                .unwrap();
+
+            match (event.source(), &event) {
+                ("base", MuxEvent::Message { .. }) => base_messages += 1,
+                ("ssr", MuxEvent::Message { .. }) => ssr_messages += 1,
+                ("ssr", MuxEvent::Disconnected { .. }) => panic!("ssr restarted"),
+                (
+                    "missing",
+                    MuxEvent::Disconnected {
+                        reason, retry_in, ..
+                    },
+                ) => {
+                    missing_failures += 1;
+                    assert!(reason.contains("NONE") && reason.contains("404"));
+                    assert!(*retry_in <= Duration::from_millis(40));
+                },
+                // Mounted, but closed before any message: backing off
+                ("empty", MuxEvent::Disconnected { retry_in, .. }) => {
+                    empty_delays.push(retry_in.as_millis())
+                },
+                _ => {},
+            }
+        }
+
+        assert_eq!(&empty_delays[..3], &[10, 20, 40]);
+        assert!(base.requests().len() >= 2);
+
+        events.close();
+        timeout(Duration::from_secs(5), async {
+            while events.next().await.is_some() {}
+        })
+        .await
+        .unwrap();
+    }
+    #[tokio::test]
+    async fn test_multiplexer_mount_timeout() {
+        let caster = MockCaster::default()
+            .with_mount("SILENT", MockMount::silent())
+            .start()
+            .await
+            .unwrap();
+
+        let mut events = NtripMultiplexer::default()
+            .with_source(MuxSource::new(
+                "silent",
+                caster.config(),
+                NtripCredentials::default(),
+                "SILENT",
+            ))
+            .with_retry_delay(Duration::from_millis(10), Duration::from_millis(10))
+            .with_mount_timeout(Duration::from_millis(100))
+            .start();
+
+        // Given up on, then mounted again
+        for _ in 0..2 {
+            let event = timeout(Duration::from_secs(5), events.next())
+                .await
+                .unwrap()
+                .unwrap();
+            match event {
+                MuxEvent::Disconnected { reason, .. } => assert!(reason.contains("no response")),
+                e => panic!("unexpected event {:?}", e),
+            }
+        }
+        assert!(caster.requests().len() >= 2);
+
+        // Closed while waiting for the response head
+        let mut events = NtripMultiplexer::default()
+            .with_source(MuxSource::new(
+                "silent",
+                caster.config(),
+                NtripCredentials::default(),
+                "SILENT",
+            ))
+            .start();
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        events.close();
+        assert!(timeout(Duration::from_secs(5), events.next())
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
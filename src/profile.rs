@@ -0,0 +1,327 @@
+//! Named profiles
+//!
+//! A [ProfileFile] describes casters, credentials and mounts in one TOML or
+//! JSON file. Each [Profile] refers to a caster and a credentials entry by
+//! name, so a caster account is declared once and shared by many mounts:
+//!
+//! ```toml
+//! [casters.rtk2go]
+//! host = "rtk2go.com"
+//! port = 2101
+//! use_tls = false
+//!
+//! [credentials.rtk2go]
+//! source = "static"
+//! user = "me@example.com"
+//! pass = "none"
+//!
+//! [credentials.corp]
+//! source = "env"
+//! user_var = "CORP_NTRIP_USER"
+//! pass_var = "CORP_NTRIP_PASS"
+//!
+//! [profiles.valdm]
+//! caster = "rtk2go"
+//! credentials = "rtk2go"
+//! mount = "VALDM"
+//! messages = "1005,1074-1127"
+//! reconnect = { initial_delay = 500, max_delay = 30000 }
+//! ```
+//!
+//! Credentials entries are `static` ([NtripCredentials] fields), `env`
+//! ([EnvCredentials] fields), `file` ([FileCredentials], `path`) or `netrc`
+//! (`path`, `$NETRC` or `~/.netrc` when not set).
+//!
+//! ```no_run
+//! use ntrip_client::ProfileFile;
+//!
+//! # async fn run() -> Result<(), ntrip_client::NtripClientError> {
+//! let profiles = ProfileFile::load("ntrip.toml")?;
+//!
+//! let mut handle = profiles.mount("valdm").await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{collections::BTreeMap, path::PathBuf, time::Duration};
+
+use futures::future::BoxFuture;
+
+use crate::{
+    client::{NtripClient, NtripHandle},
+    config::{MountOptions, NtripConfig, NtripCredentials},
+    credentials::{CredentialProvider, EnvCredentials, FileCredentials, NetrcCredentials},
+    multiplex::{MuxSource, NtripMultiplexer},
+    NtripClientError,
+};
+
+/// Casters, credentials and profiles, see the [module](crate::profile) documentation
+#[derive(Clone, Default, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ProfileFile {
+    /// Caster configurations, by name
+    pub casters: BTreeMap<String, NtripConfig>,
+    /// Credentials, by name
+    pub credentials: BTreeMap<String, CredentialSource>,
+    /// Profiles, by name
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// Credentials entry of a [ProfileFile]
+#[derive(Clone, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "source", rename_all = "lowercase")]
+pub enum CredentialSource {
+    /// Credentials written in the file
+    Static(NtripCredentials),
+    /// Credentials read from environment variables
+    Env(EnvCredentials),
+    /// Credentials read from a file
+    File(FileCredentials),
+    /// Credentials read from a `.netrc` file, `$NETRC` or `~/.netrc` by default
+    Netrc {
+        #[serde(default)]
+        path: Option<PathBuf>,
+    },
+}
+
+impl CredentialProvider for CredentialSource {
+    fn credentials<'a>(
+        &'a self,
+        config: &'a NtripConfig,
+    ) -> BoxFuture<'a, Result<NtripCredentials, NtripClientError>> {
+        match self {
+            Self::Static(creds) => creds.credentials(config),
+            Self::Env(env) => env.credentials(config),
+            Self::File(file) => file.credentials(config),
+            Self::Netrc { path } => Box::pin(async move {
+                let netrc = match path {
+                    Some(path) => NetrcCredentials::new(path),
+                    None => NetrcCredentials::from_home()?,
+                };
+                netrc.credentials(config).await
+            }),
+        }
+    }
+}
+
+/// Mount of a [ProfileFile]
+#[derive(Clone, Default, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Profile {
+    /// Name of the caster entry
+    pub caster: String,
+    /// Name of the credentials entry, anonymous when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credentials: Option<String>,
+    /// Mount point name
+    pub mount: String,
+    /// RTCM messages to deliver, e.g. `1005,1006,1074-1127,!1230`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub messages: String,
+    /// Mount again after failures, when multiplexed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reconnect: Option<ReconnectPolicy>,
+}
+
+/// Delays before mounting a [Profile] again, doubled on each consecutive failure
+#[derive(Clone, Copy, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ReconnectPolicy {
+    /// First delay (ms)
+    pub initial_delay: u64,
+    /// Longest delay (ms)
+    pub max_delay: u64,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: 1000,
+            max_delay: 60_000,
+        }
+    }
+}
+
+impl ProfileFile {
+    /// Loads a TOML (`.toml`) or JSON (`.json`) profile file
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self, NtripClientError> {
+        let path = path.into();
+        let content = std::fs::read_to_string(&path)?;
+
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Self::from_toml(&content),
+            Some("json") => Self::from_json(&content),
+            _ => Err(NtripClientError::Profile(format!(
+                "unknown profile file format: {}",
+                path.display()
+            ))),
+        }
+    }
+
+    /// Parses TOML profiles
+    pub fn from_toml(content: &str) -> Result<Self, NtripClientError> {
+        let file = toml::from_str::<Self>(content)
+            .map_err(|e| NtripClientError::Profile(e.message().to_string()))?;
+        file.validate()?;
+        Ok(file)
+    }
+
+    /// Parses JSON profiles
+    pub fn from_json(content: &str) -> Result<Self, NtripClientError> {
+        let file = serde_json::from_str::<Self>(content)
+            .map_err(|e| NtripClientError::Profile(e.to_string()))?;
+        file.validate()?;
+        Ok(file)
+    }
+
+    /// Checks that every profile refers to existing entries
+    /// and has a valid message filter
+    pub fn validate(&self) -> Result<(), NtripClientError> {
+        for name in self.profiles.keys() {
+            self.resolve(name)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the [Profile] of this name
+    pub fn profile(&self, name: &str) -> Result<&Profile, NtripClientError> {
+        self.profiles
+            .get(name)
+            .ok_or_else(|| NtripClientError::Profile(format!("unknown profile \"{}\"", name)))
+    }
+
+    /// Resolves a profile into its caster, credentials and [MountOptions]
+    fn resolve(
+        &self,
+        name: &str,
+    ) -> Result<(NtripConfig, CredentialSource, MountOptions), NtripClientError> {
+        let profile = self.profile(name)?;
+
+        let config = self.casters.get(&profile.caster).ok_or_else(|| {
+            NtripClientError::Profile(format!(
+                "profile \"{}\": unknown caster \"{}\"",
+                name, profile.caster
+            ))
+        })?;
+
+        let credentials = match &profile.credentials {
+            Some(creds) => self.credentials.get(creds).cloned().ok_or_else(|| {
+                NtripClientError::Profile(format!(
+                    "profile \"{}\": unknown credentials \"{}\"",
+                    name, creds
+                ))
+            })?,
+            None => CredentialSource::Static(NtripCredentials::default()),
+        };
+
+        let options = MountOptions::default().with_filter(profile.messages.parse()?);
+
+        Ok((config.clone(), credentials, options))
+    }
+
+    /// Builds an [NtripClient] connecting to the caster of this profile
+    pub async fn client(&self, name: &str) -> Result<NtripClient, NtripClientError> {
+        let (config, credentials, _) = self.resolve(name)?;
+        NtripClient::with_credential_provider(config, credentials).await
+    }
+
+    /// Mounts this profile, see [NtripClient::mount_with_options]
+    pub async fn mount(&self, name: &str) -> Result<NtripHandle, NtripClientError> {
+        let (config, credentials, options) = self.resolve(name)?;
+        let mount = &self.profile(name)?.mount;
+
+        NtripClient::with_credential_provider(config, credentials)
+            .await?
+            .mount_with_options(mount, options)
+            .await
+    }
+
+    /// Returns a [MuxSource] of this profile, named after it,
+    /// with its reconnect policy
+    pub fn source(&self, name: &str) -> Result<MuxSource, NtripClientError> {
+        let (config, credentials, options) = self.resolve(name)?;
+        let profile = self.profile(name)?;
+
+        let mut source =
+            MuxSource::new(name, config, credentials, &profile.mount).with_options(options);
+        if let Some(reconnect) = profile.reconnect {
+            source = source.with_retry_delay(
+                Duration::from_millis(reconnect.initial_delay),
+                Duration::from_millis(reconnect.max_delay),
+            );
+        }
+
+        Ok(source)
+    }
+
+    /// Returns an [NtripMultiplexer] of these profiles
+    pub fn multiplexer<'a>(
+        &self,
+        names: impl IntoIterator<Item = &'a str>,
+    ) -> Result<NtripMultiplexer, NtripClientError> {
+        names
+            .into_iter()
+            .try_fold(NtripMultiplexer::default(), |mux, name| {
+                Ok(mux.with_source(self.source(name)?))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use rtcm_rs::Message;
+
+    use super::*;
+    use crate::mock::{station_frame, MockCaster, MockMount};
+
+    #[tokio::test]
+    async fn test_profile_mount() {
+        let data = [station_frame(1), station_frame(2)].concat();
+        let caster = MockCaster::default()
+            .with_mount("VALDM", MockMount::icy().with_data(&data).then_close())
+            .start()
+            .await
+            .unwrap();
+
+        let toml = format!(
+            r#"
+            [casters.local]
+            host = "127.0.0.1"
+            port = {}
+            use_tls = false
+
+            [credentials.local]
+            source = "static"
+            user = "user"
+            pass = "pass"
+
+            [profiles.valdm]
+            caster = "local"
+            credentials = "local"
+            mount = "VALDM"
+            messages = "1005"
+            reconnect = {{ initial_delay = 10 }}
+            "#,
+            caster.addr().port()
+        );
+
+        let profiles = ProfileFile::from_toml(&toml).unwrap();
+        let json = serde_json::to_string(&profiles).unwrap();
+        assert_eq!(ProfileFile::from_json(&json).unwrap(), profiles);
+
+        let source = profiles.source("valdm").unwrap();
+        assert_eq!(
+            source.retry_delay,
+            Some((Duration::from_millis(10), Duration::from_secs(60)))
+        );
+
+        let mut handle = profiles.mount("valdm").await.unwrap();
+        assert!(matches!(handle.next().await, Some(Message::Msg1005(_))));
+        assert!(matches!(handle.next().await, Some(Message::Msg1005(_))));
+
+        assert!(profiles.mount("none").await.is_err());
+        assert!(
+            ProfileFile::from_toml(&toml.replace("caster = \"local\"", "caster = \"x\"")).is_err()
+        );
+    }
+}
@@ -0,0 +1,430 @@
+//! NTRIP request and response framing
+//!
+//! Builds NTRIP 2.0 (HTTP/1.1) mount requests, reads response heads of every
+//! flavour casters answer with (`HTTP/1.x`, NTRIP 1.0 `ICY`, `SOURCETABLE`, `RTSP/1.0`)
+//! and decodes chunked transfer encoding.
+
+use http::{
+    header::{ACCEPT, AUTHORIZATION, HOST, USER_AGENT},
+    HeaderMap, HeaderValue,
+};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::debug;
+
+use crate::{auth::AuthChallenge, config::NtripConfig, proxy::format_host, NtripClientError};
+
+/// Largest accepted response head
+const MAX_HEAD: usize = 16 * 1024;
+
+/// Largest accepted chunk size line
+const MAX_CHUNK_LINE: usize = 1024;
+
+/// Characters escaped in a mount name (path segment)
+const MOUNT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Percent-encodes a mount name
+pub(crate) fn encode_mount(mount: &str) -> String {
+    utf8_percent_encode(mount, MOUNT).to_string()
+}
+
+/// `Host` header value: port omitted when it is the scheme default
+pub(crate) fn host_header(config: &NtripConfig) -> String {
+    let default_port = if config.use_tls { 443 } else { 80 };
+
+    if config.port == default_port {
+        format_host(&config.host)
+    } else {
+        format!("{}:{}", format_host(&config.host), config.port)
+    }
+}
+
+/// Client `User-Agent`, NTRIP casters expect it to start with "NTRIP"
+pub(crate) fn user_agent() -> String {
+    format!(
+        "NTRIP {}/{}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+/// Builds an NTRIP 2.0 mount request
+pub(crate) fn mount_request(
+    config: &NtripConfig,
+    mount: &str,
+    authorization: Option<&HeaderValue>,
+) -> Result<String, NtripClientError> {
+    let mut headers = HeaderMap::new();
+    headers.append(HOST, HeaderValue::from_str(&host_header(config))?);
+    headers.append("Ntrip-Version", HeaderValue::from_static("Ntrip/2.0"));
+    headers.append(USER_AGENT, HeaderValue::from_str(&user_agent())?);
+    headers.append(ACCEPT, HeaderValue::from_static("*/*"));
+
+    if let Some(authorization) = authorization {
+        headers.append(AUTHORIZATION, authorization.clone());
+    }
+
+    debug!("Headers: {:#?}", headers);
+
+    let mut request = format!("GET /{} HTTP/1.1\r\n", encode_mount(mount));
+
+    for (name, value) in headers.iter() {
+        request.push_str(&format!("{}: {}\r\n", name.as_str(), value.to_str()?));
+    }
+
+    request.push_str("\r\n");
+    Ok(request)
+}
+
+/// Response status line and headers
+#[derive(Clone, Default, PartialEq, Debug)]
+pub(crate) struct ResponseHead {
+    /// Complete status line, e.g. "HTTP/1.1 200 OK"
+    pub status_line: String,
+    /// Status code
+    pub status: u16,
+    /// Header names and values
+    pub headers: Vec<(String, String)>,
+}
+
+impl ResponseHead {
+    /// Parses a response head. Returns None when more data is needed,
+    /// or the head with its length.
+    pub fn parse(buff: &[u8]) -> Result<Option<(Self, usize)>, NtripClientError> {
+        let known = ["HTTP/", "ICY ", "SOURCETABLE ", "RTSP/"];
+
+        if !known.iter().any(|k| {
+            let len = k.len().min(buff.len());
+            buff[..len] == k.as_bytes()[..len]
+        }) {
+            return Err(invalid_response(buff));
+        }
+
+        let Some(line_end) = find(buff, b"\n") else {
+            return Self::need_more(buff);
+        };
+
+        let status_line = String::from_utf8_lossy(&buff[..line_end])
+            .trim_end()
+            .to_string();
+
+        let status = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse::<u16>().ok())
+            .ok_or_else(|| invalid_response(buff))?;
+
+        // NTRIP 1.0 casters may start streaming right after the status line
+        let icy = status_line.starts_with("ICY ");
+
+        let mut headers = Vec::new();
+        let mut offset = line_end + 1;
+
+        loop {
+            let Some(len) = find(&buff[offset..], b"\n") else {
+                if icy {
+                    break;
+                }
+                return Self::need_more(buff);
+            };
+
+            let line = String::from_utf8_lossy(&buff[offset..offset + len]);
+            let line = line.trim_end();
+
+            if line.is_empty() {
+                offset += len + 1;
+                break;
+            }
+
+            match line.split_once(':') {
+                Some((name, value)) if !name.is_empty() && name.is_ascii() => {
+                    headers.push((name.trim().to_string(), value.trim().to_string()));
+                },
+                _ if icy => break,
+                _ => return Err(invalid_response(buff)),
+            }
+
+            offset += len + 1;
+        }
+
+        Ok(Some((
+            Self {
+                status_line,
+                status,
+                headers,
+            },
+            offset,
+        )))
+    }
+
+    fn need_more(buff: &[u8]) -> Result<Option<(Self, usize)>, NtripClientError> {
+        if buff.len() > MAX_HEAD {
+            Err(NtripClientError::ResponseError(
+                "response head too large".into(),
+            ))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Returns a header value
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns true for a successful mount response. A `SOURCETABLE 200 OK`
+    /// is how NTRIP 1.0 casters answer an unknown mount.
+    pub fn is_success(&self) -> bool {
+        self.status == 200 && !self.status_line.starts_with("SOURCETABLE")
+    }
+
+    /// Returns true if the body uses chunked transfer encoding
+    pub fn is_chunked(&self) -> bool {
+        self.header("Transfer-Encoding")
+            .is_some_and(|te| te.to_ascii_lowercase().contains("chunked"))
+    }
+
+    /// Returns the `WWW-Authenticate` challenges
+    pub fn challenges(&self) -> Vec<AuthChallenge> {
+        self.headers
+            .iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case("WWW-Authenticate"))
+            .flat_map(|(_, v)| AuthChallenge::parse_all(v))
+            .collect()
+    }
+
+    /// Reads a response head. The head is removed from `buff`,
+    /// the data received after it is left in place.
+    pub async fn read(
+        sock: &mut (impl AsyncRead + Unpin),
+        buff: &mut Vec<u8>,
+    ) -> Result<Self, NtripClientError> {
+        loop {
+            if !buff.is_empty() {
+                if let Some((head, len)) = Self::parse(buff)? {
+                    buff.drain(..len);
+                    debug!("Response: {}", head.status_line);
+                    return Ok(head);
+                }
+            }
+
+            if sock.read_buf(buff).await? == 0 {
+                return Err(match buff.is_empty() {
+                    true => NtripClientError::ResponseError("empty response".into()),
+                    false => invalid_response(buff),
+                });
+            }
+        }
+    }
+}
+
+fn find(buff: &[u8], pattern: &[u8]) -> Option<usize> {
+    buff.windows(pattern.len()).position(|w| w == pattern)
+}
+
+fn invalid_response(buff: &[u8]) -> NtripClientError {
+    let line = buff.split(|b| *b == b'\n').next().unwrap_or_default();
+    NtripClientError::ResponseError(format!(
+        "invalid response \"{}\"",
+        String::from_utf8_lossy(&line[..line.len().min(64)]).trim_end()
+    ))
+}
+
+/// Chunked transfer encoding state
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub(crate) enum ChunkState {
+    #[default]
+    Size,
+    Data(usize),
+    DataEnd,
+    Trailer,
+    Done,
+}
+
+/// Decodes the response body into the RTCM byte stream
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub(crate) enum BodyDecoder {
+    /// Body sent as is
+    #[default]
+    Identity,
+    /// Chunked transfer encoding
+    Chunked(ChunkState),
+}
+
+impl BodyDecoder {
+    /// Returns the decoder of this response
+    pub fn new(head: &ResponseHead) -> Self {
+        if head.is_chunked() {
+            Self::Chunked(ChunkState::default())
+        } else {
+            Self::Identity
+        }
+    }
+
+    /// Moves the decoded content of `input` to `output`, partial
+    /// chunk headers are left in `input`. Returns true at the end of the body.
+    pub fn decode(
+        &mut self,
+        input: &mut Vec<u8>,
+        output: &mut Vec<u8>,
+    ) -> Result<bool, NtripClientError> {
+        let Self::Chunked(state) = self else {
+            output.append(input);
+            return Ok(false);
+        };
+
+        loop {
+            match *state {
+                ChunkState::Size | ChunkState::Trailer => {
+                    let Some(end) = find(input, b"\r\n") else {
+                        if input.len() > MAX_CHUNK_LINE {
+                            return Err(NtripClientError::ResponseError(
+                                "invalid chunk header".into(),
+                            ));
+                        }
+                        return Ok(false);
+                    };
+
+                    let line = String::from_utf8_lossy(&input[..end]).to_string();
+                    input.drain(..end + 2);
+
+                    *state = match *state {
+                        ChunkState::Size => {
+                            let size = line.split(';').next().unwrap_or_default().trim();
+                            match usize::from_str_radix(size, 16) {
+                                Ok(0) => ChunkState::Trailer,
+                                Ok(size) => ChunkState::Data(size),
+                                Err(_) => {
+                                    return Err(NtripClientError::ResponseError(format!(
+                                        "invalid chunk size \"{}\"",
+                                        size
+                                    )))
+                                },
+                            }
+                        },
+                        _ if line.is_empty() => ChunkState::Done,
+                        _ => ChunkState::Trailer,
+                    };
+                },
+                ChunkState::Data(remaining) => {
+                    if input.is_empty() {
+                        return Ok(false);
+                    }
+
+                    let n = remaining.min(input.len());
+                    output.extend(input.drain(..n));
+
+                    *state = match remaining - n {
+                        0 => ChunkState::DataEnd,
+                        remaining => ChunkState::Data(remaining),
+                    };
+                },
+                ChunkState::DataEnd => {
+                    if input.len() < 2 {
+                        return Ok(false);
+                    }
+
+                    if input[..2] != *b"\r\n" {
+                        return Err(NtripClientError::ResponseError(
+                            "invalid chunk terminator".into(),
+                        ));
+                    }
+
+                    input.drain(..2);
+                    *state = ChunkState::Size;
+                },
+                ChunkState::Done => return Ok(true),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mount_request() {
+        let config = NtripConfig::default().with_host("::1").with_port(2101);
+        let request = mount_request(&config, "MY MOUNT", None).unwrap();
+
+        assert!(request.starts_with("GET /MY%20MOUNT HTTP/1.1\r\nhost: [::1]:2101\r\n"));
+        assert!(request.contains("ntrip-version: Ntrip/2.0\r\n"));
+        assert!(request.ends_with("\r\n\r\n"));
+
+        let config = config.with_host("caster.org").with_port(443).with_tls();
+        assert_eq!(host_header(&config), "caster.org");
+    }
+
+    #[test]
+    fn test_response_head() {
+        // Head split across reads
+        let data = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\xd3";
+        assert!(ResponseHead::parse(&data[..20]).unwrap().is_none());
+
+        let (head, len) = ResponseHead::parse(data).unwrap().unwrap();
+        assert_eq!(head.status, 200);
+        assert!(head.is_success() && head.is_chunked());
+        assert_eq!(&data[len..], b"\xd3");
+
+        // NTRIP 1.0: data may follow the status line directly
+        let (head, len) = ResponseHead::parse(b"ICY 200 OK\r\n\xd3\x00")
+            .unwrap()
+            .unwrap();
+        assert!(head.is_success());
+        assert_eq!(len, 12);
+
+        let (head, len) = ResponseHead::parse(b"ICY 200 OK\r\nServer: x\r\n\r\n\xd3")
+            .unwrap()
+            .unwrap();
+        assert_eq!(head.header("server"), Some("x"));
+        assert_eq!(len, 25);
+
+        let (head, _) = ResponseHead::parse(b"SOURCETABLE 200 OK\r\n\r\n")
+            .unwrap()
+            .unwrap();
+        assert!(!head.is_success());
+
+        assert!(ResponseHead::parse(b"\x00\x01\x02").is_err());
+        assert!(ResponseHead::parse(b"HTTP/1.1 OK\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_chunked_decoder() {
+        let mut decoder = BodyDecoder::Chunked(ChunkState::default());
+        let mut output = Vec::new();
+
+        let body = b"3;ext=1\r\nabc\r\n2\r\nde\r\n0\r\nTrailer: x\r\n\r\n";
+
+        // Byte by byte
+        let mut input = Vec::new();
+        let mut done = false;
+        for b in body {
+            input.push(*b);
+            done = decoder.decode(&mut input, &mut output).unwrap();
+        }
+
+        assert!(done);
+        assert_eq!(output, b"abcde");
+        assert!(input.is_empty());
+
+        let mut decoder = BodyDecoder::Chunked(ChunkState::default());
+        assert!(decoder
+            .decode(&mut b"2\r\nabcd".to_vec(), &mut Vec::new())
+            .is_err());
+    }
+}
@@ -0,0 +1,620 @@
+//! Recording of mounted streams to disk
+//!
+//! A [Recorder] writes the data received from a mount into segment files
+//! named `{prefix}-{segment:04}.rtcm`. Each segment starts with a short text
+//! header describing the connection ([RecordingHeader]), followed by the
+//! received bytes. When enabled, a `{prefix}-{segment:04}.idx` sidecar lists
+//! one `offset,length,seconds.nanoseconds` line per written chunk, where
+//! `offset` is relative to the start of the segment file.
+//!
+//! A [Recorder] attached to a mount with
+//! [NtripHandle::record](crate::NtripHandle::record) writes on its own
+//! thread: a slow disk delays the recording, not the stream.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Sender},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::sync::oneshot;
+use tracing::{debug, error};
+
+use crate::{config::NtripConfig, proxy::ProxyConfig, snip::MountInfo, NtripClientError};
+
+/// First line of every recording segment
+pub const RECORDING_MAGIC: &str = "NTRIP-RECORDING/1";
+
+/// What the [Recorder] captures from a mount
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub enum RecordMode {
+    /// Valid RTCM frames only, one index entry per frame
+    #[default]
+    Frames,
+    /// Every body byte received (after HTTP chunked transfer decoding),
+    /// one index entry per read
+    Raw,
+}
+
+/// [Recorder] options
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct RecorderOptions {
+    /// Data to capture
+    pub mode: RecordMode,
+    /// Write a timestamp index sidecar next to each segment
+    pub index: bool,
+    /// Start a new segment once the current one reaches this size (in bytes)
+    pub max_segment_size: Option<u64>,
+    /// Start a new segment once the current one is older than this
+    pub max_segment_duration: Option<Duration>,
+}
+
+impl RecorderOptions {
+    /// Copies and returns [RecorderOptions] with updated [RecordMode]
+    pub fn with_mode(&self, mode: RecordMode) -> Self {
+        let mut s = self.clone();
+        s.mode = mode;
+        s
+    }
+
+    /// Copies and returns [RecorderOptions] with the timestamp index active
+    pub fn with_index(&self) -> Self {
+        let mut s = self.clone();
+        s.index = true;
+        s
+    }
+
+    /// Copies and returns [RecorderOptions] with size based rotation
+    pub fn with_max_segment_size(&self, bytes: u64) -> Self {
+        let mut s = self.clone();
+        s.max_segment_size = Some(bytes);
+        s
+    }
+
+    /// Copies and returns [RecorderOptions] with time based rotation
+    pub fn with_max_segment_duration(&self, duration: Duration) -> Self {
+        let mut s = self.clone();
+        s.max_segment_duration = Some(duration);
+        s
+    }
+}
+
+/// Describes where a recording comes from, written at the start of every segment
+#[derive(Clone, PartialEq, Debug)]
+pub struct RecordingHeader {
+    /// [NtripConfig] used to connect, without the proxy password
+    pub config: NtripConfig,
+    /// Name of the mount point
+    pub mount: String,
+    /// Sourcetable entry of the mount point, if known
+    pub mount_info: Option<MountInfo>,
+}
+
+impl RecordingHeader {
+    /// Builds a [RecordingHeader] for this mount
+    pub fn new(config: &NtripConfig, mount: &str) -> Self {
+        Self {
+            config: config.clone(),
+            mount: mount.to_string(),
+            mount_info: None,
+        }
+    }
+
+    /// Copies and returns [RecordingHeader] with the sourcetable entry
+    pub fn with_mount_info(&self, info: &MountInfo) -> Self {
+        let mut s = self.clone();
+        s.mount_info = Some(info.clone());
+        s
+    }
+
+    /// Parses a [RecordingHeader] from the start of a segment.
+    /// Returns the header and its length in bytes (including the terminating blank line),
+    /// or None if `data` does not start with a recording header.
+    pub fn parse(data: &[u8]) -> Option<(Self, usize)> {
+        if !data.starts_with(RECORDING_MAGIC.as_bytes()) {
+            return None;
+        }
+
+        let end = data.windows(2).position(|w| w == b"\n\n")? + 2;
+        let text = std::str::from_utf8(&data[..end]).ok()?;
+
+        let mut config = NtripConfig::default();
+        let mut mount = String::new();
+        let mut mount_info = None;
+
+        for line in text.lines().skip(1) {
+            let Some((key, value)) = line.split_once(": ") else {
+                continue;
+            };
+
+            match key {
+                "Host" => config.host = value.to_string(),
+                "Port" => config.port = value.parse().ok()?,
+                "Tls" => config.use_tls = value.parse().ok()?,
+                "Transport" => config.transport = value.parse().ok()?,
+                "Auth" => config.auth = value.parse().ok()?,
+                // The password is redacted
+                "Proxy" => {
+                    let mut proxy = value.parse::<ProxyConfig>().ok()?;
+                    proxy.pass = None;
+                    config.proxy = Some(proxy);
+                },
+                "LocalAddress" => config.local_address = Some(value.parse().ok()?),
+                "Interface" => config.interface = Some(value.to_string()),
+                "TlsRoot" => config.tls.extra_roots.push(value.into()),
+                "TlsNativeRoots" => config.tls.native_roots = value.parse().ok()?,
+                "TlsNoBuiltinRoots" => config.tls.no_builtin_roots = value.parse().ok()?,
+                "TlsClientCert" => config.tls.client_cert = Some(value.into()),
+                "TlsClientKey" => config.tls.client_key = Some(value.into()),
+                "TlsPin" => config.tls.pinned_keys.push(value.to_string()),
+                "TlsServerName" => config.tls.server_name = Some(value.to_string()),
+                "KeepaliveIdle" => config.socket.keepalive_idle = Some(value.parse().ok()?),
+                "KeepaliveInterval" => config.socket.keepalive_interval = Some(value.parse().ok()?),
+                "KeepaliveCount" => config.socket.keepalive_count = Some(value.parse().ok()?),
+                "Nodelay" => config.socket.nodelay = value.parse().ok()?,
+                "RecvBuffer" => config.socket.recv_buffer_size = Some(value.parse().ok()?),
+                "UserTimeout" => config.socket.user_timeout = Some(value.parse().ok()?),
+                "Mount" => mount = value.to_string(),
+                "Sourcetable" => mount_info = MountInfo::parse(value),
+                _ => {},
+            }
+        }
+
+        Some((
+            Self {
+                config,
+                mount,
+                mount_info,
+            },
+            end,
+        ))
+    }
+
+    /// Formats the header of a segment, including the terminating blank line
+    pub(crate) fn format(&self, segment: usize, started: SystemTime) -> String {
+        let mut header = format!("{}\n", RECORDING_MAGIC);
+        for (key, value) in config_fields(&self.config) {
+            header.push_str(&format!("{}: {}\n", key, value));
+        }
+
+        header.push_str(&format!(
+            "Mount: {}\nSegment: {}\nStarted: {}\n",
+            self.mount,
+            segment,
+            format_time(started)
+        ));
+
+        if let Some(info) = &self.mount_info {
+            header.push_str(&format!("Sourcetable: {}\n", info));
+        }
+
+        header.push('\n');
+        header
+    }
+}
+
+/// Writes a mounted stream to disk, see the [module](crate::recorder) documentation.
+pub struct Recorder {
+    prefix: PathBuf,
+    header: RecordingHeader,
+    options: RecorderOptions,
+    segment: usize,
+    data: BufWriter<File>,
+    index: Option<BufWriter<File>>,
+    offset: u64,
+    payload: u64,
+    opened: Instant,
+}
+
+impl Recorder {
+    /// Creates a [Recorder] and opens its first segment.
+    ///
+    /// ## Input
+    /// - prefix: path prefix of the segment files
+    /// - header: [RecordingHeader] written to each segment
+    /// - options: [RecorderOptions]
+    pub fn create(
+        prefix: impl AsRef<Path>,
+        header: RecordingHeader,
+        options: RecorderOptions,
+    ) -> Result<Self, NtripClientError> {
+        let prefix = prefix.as_ref().to_path_buf();
+        let (data, index, offset) = Self::open_segment(&prefix, &header, &options, 0)?;
+
+        Ok(Self {
+            prefix,
+            header,
+            options,
+            segment: 0,
+            data,
+            index,
+            offset,
+            payload: 0,
+            opened: Instant::now(),
+        })
+    }
+
+    /// Returns the [RecordMode] of this [Recorder]
+    pub fn mode(&self) -> RecordMode {
+        self.options.mode
+    }
+
+    /// Returns the path of the segment currently being written
+    pub fn segment_path(&self) -> PathBuf {
+        segment_path(&self.prefix, self.segment, "rtcm")
+    }
+
+    /// Returns the path of the index sidecar of the current segment
+    pub fn index_path(&self) -> Option<PathBuf> {
+        self.index
+            .as_ref()
+            .map(|_| segment_path(&self.prefix, self.segment, "idx"))
+    }
+
+    /// Writes a chunk of data (a frame or a raw read) received at `received`,
+    /// rotating to a new segment first if needed.
+    pub fn write(&mut self, chunk: &[u8], received: SystemTime) -> Result<(), NtripClientError> {
+        if self.should_rotate(chunk.len()) {
+            self.rotate()?;
+        }
+
+        self.data.write_all(chunk)?;
+
+        if let Some(index) = &mut self.index {
+            writeln!(
+                index,
+                "{},{},{}",
+                self.offset,
+                chunk.len(),
+                format_time(received)
+            )?;
+        }
+
+        self.offset += chunk.len() as u64;
+        self.payload += chunk.len() as u64;
+
+        Ok(())
+    }
+
+    /// Flushes pending data to disk
+    pub fn flush(&mut self) -> Result<(), NtripClientError> {
+        self.data.flush()?;
+        if let Some(index) = &mut self.index {
+            index.flush()?;
+        }
+        Ok(())
+    }
+
+    fn should_rotate(&self, len: usize) -> bool {
+        // Never leave a segment empty
+        if self.payload == 0 {
+            return false;
+        }
+
+        if let Some(max) = self.options.max_segment_size {
+            if self.offset + len as u64 > max {
+                return true;
+            }
+        }
+
+        if let Some(max) = self.options.max_segment_duration {
+            if self.opened.elapsed() >= max {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    fn rotate(&mut self) -> Result<(), NtripClientError> {
+        self.flush()?;
+
+        let segment = self.segment + 1;
+        let (data, index, offset) =
+            Self::open_segment(&self.prefix, &self.header, &self.options, segment)?;
+
+        self.segment = segment;
+        self.data = data;
+        self.index = index;
+        self.offset = offset;
+        self.payload = 0;
+        self.opened = Instant::now();
+
+        Ok(())
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn open_segment(
+        prefix: &Path,
+        header: &RecordingHeader,
+        options: &RecorderOptions,
+        segment: usize,
+    ) -> Result<(BufWriter<File>, Option<BufWriter<File>>, u64), NtripClientError> {
+        let path = segment_path(prefix, segment, "rtcm");
+        debug!("Opening recording segment {}", path.display());
+
+        let mut data = BufWriter::new(File::create(&path)?);
+        let header = header.format(segment, SystemTime::now());
+        data.write_all(header.as_bytes())?;
+
+        let index = if options.index {
+            let path = segment_path(prefix, segment, "idx");
+            Some(BufWriter::new(File::create(path)?))
+        } else {
+            None
+        };
+
+        Ok((data, index, header.len() as u64))
+    }
+}
+
+enum WriterCommand {
+    Write(Vec<u8>, SystemTime),
+    Flush,
+}
+
+/// [Recorder] running on its own thread, fed by the reader task of a mount
+pub(crate) struct RecorderWriter {
+    mode: RecordMode,
+    tx: Sender<WriterCommand>,
+    done: oneshot::Receiver<Recorder>,
+}
+
+impl RecorderWriter {
+    /// Moves this [Recorder] to a writer thread.
+    /// Recording stops on the first write error.
+    pub(crate) fn spawn(mut recorder: Recorder) -> Self {
+        let (tx, rx) = channel();
+        let (done_tx, done) = oneshot::channel();
+        let mode = recorder.mode();
+
+        thread::spawn(move || {
+            let mut failed = false;
+
+            while let Ok(command) = rx.recv() {
+                if failed {
+                    continue;
+                }
+
+                let result = match command {
+                    WriterCommand::Write(chunk, received) => recorder.write(&chunk, received),
+                    WriterCommand::Flush => recorder.flush(),
+                };
+
+                if let Err(e) = result {
+                    error!("Recording error: {}, recording stopped", e);
+                    failed = true;
+                }
+            }
+
+            if !failed {
+                if let Err(e) = recorder.flush() {
+                    error!("Recording flush error: {}", e);
+                }
+            }
+
+            let _ = done_tx.send(recorder);
+        });
+
+        Self { mode, tx, done }
+    }
+
+    /// Queues received data, if this [RecordMode] is captured
+    pub(crate) fn write(&self, mode: RecordMode, chunk: &[u8], received: SystemTime) {
+        if mode == self.mode {
+            let _ = self.tx.send(WriterCommand::Write(chunk.to_vec(), received));
+        }
+    }
+
+    /// Queues a flush to disk
+    pub(crate) fn flush(&self) {
+        let _ = self.tx.send(WriterCommand::Flush);
+    }
+
+    /// Waits for the queued data to be written, and returns the flushed [Recorder]
+    pub(crate) async fn finish(self) -> Option<Recorder> {
+        drop(self.tx);
+        self.done.await.ok()
+    }
+}
+
+/// Header lines describing every [NtripConfig] field, defaults omitted.
+/// The proxy password is redacted, TLS files are referred to by path.
+fn config_fields(config: &NtripConfig) -> Vec<(&'static str, String)> {
+    let mut fields = vec![
+        ("Host", config.host.clone()),
+        ("Port", config.port.to_string()),
+        ("Tls", config.use_tls.to_string()),
+        ("Transport", config.transport.to_string()),
+        ("Auth", config.auth.to_string()),
+    ];
+
+    let optional = |key, value: Option<String>| value.map(|value| (key, value));
+    let flag = |key, set: bool| set.then(|| (key, "true".to_string()));
+
+    let (tls, socket) = (&config.tls, &config.socket);
+
+    fields.extend(optional(
+        "Proxy",
+        config.proxy.as_ref().map(|p| p.to_string()),
+    ));
+    fields.extend(optional(
+        "LocalAddress",
+        config.local_address.map(|a| a.to_string()),
+    ));
+    fields.extend(optional("Interface", config.interface.clone()));
+
+    fields.extend(
+        tls.extra_roots
+            .iter()
+            .map(|root| ("TlsRoot", root.display().to_string())),
+    );
+    fields.extend(flag("TlsNativeRoots", tls.native_roots));
+    fields.extend(flag("TlsNoBuiltinRoots", tls.no_builtin_roots));
+    fields.extend(optional(
+        "TlsClientCert",
+        tls.client_cert.as_ref().map(|p| p.display().to_string()),
+    ));
+    fields.extend(optional(
+        "TlsClientKey",
+        tls.client_key.as_ref().map(|p| p.display().to_string()),
+    ));
+    fields.extend(tls.pinned_keys.iter().map(|pin| ("TlsPin", pin.clone())));
+    fields.extend(optional("TlsServerName", tls.server_name.clone()));
+
+    fields.extend(optional(
+        "KeepaliveIdle",
+        socket.keepalive_idle.map(|v| v.to_string()),
+    ));
+    fields.extend(optional(
+        "KeepaliveInterval",
+        socket.keepalive_interval.map(|v| v.to_string()),
+    ));
+    fields.extend(optional(
+        "KeepaliveCount",
+        socket.keepalive_count.map(|v| v.to_string()),
+    ));
+    fields.extend(flag("Nodelay", socket.nodelay));
+    fields.extend(optional(
+        "RecvBuffer",
+        socket.recv_buffer_size.map(|v| v.to_string()),
+    ));
+    fields.extend(optional(
+        "UserTimeout",
+        socket.user_timeout.map(|v| v.to_string()),
+    ));
+
+    fields
+}
+
+pub(crate) fn segment_path(prefix: &Path, segment: usize, extension: &str) -> PathBuf {
+    let mut name = prefix.as_os_str().to_os_string();
+    name.push(format!("-{:04}.{}", segment, extension));
+    PathBuf::from(name)
+}
+
+fn format_time(t: SystemTime) -> String {
+    let d = t.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{}.{:09}", d.as_secs(), d.subsec_nanos())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::{
+        auth::AuthScheme,
+        client::NtripClient,
+        config::NtripCredentials,
+        mock::{station_frame, MockCaster, MockMount},
+        socket::SocketOptions,
+        tls::TlsSettings,
+    };
+
+    #[test]
+    fn test_recorder_rotation() {
+        let dir = std::env::temp_dir().join(format!("ntrip-recorder-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let prefix = dir.join("VALDM");
+
+        let header = RecordingHeader::new(&NtripConfig::default(), "VALDM");
+        let frame = station_frame(42);
+
+        // Room for the header and two frames per segment
+        let header_len = header.format(0, SystemTime::now()).len() as u64;
+        let options = RecorderOptions::default()
+            .with_index()
+            .with_max_segment_size(header_len + 2 * frame.len() as u64);
+
+        let mut recorder = Recorder::create(&prefix, header, options).unwrap();
+        for _ in 0..5 {
+            recorder.write(&frame, SystemTime::now()).unwrap();
+        }
+        recorder.flush().unwrap();
+
+        assert_eq!(recorder.segment_path(), segment_path(&prefix, 2, "rtcm"));
+
+        let data = fs::read(segment_path(&prefix, 0, "rtcm")).unwrap();
+        assert!(data.starts_with(b"NTRIP-RECORDING/1\nHost: caster.centipede.fr\n"));
+        assert_eq!(&data[data.len() - frame.len()..], &frame[..]);
+
+        let index = fs::read_to_string(segment_path(&prefix, 0, "idx")).unwrap();
+        let offsets = index
+            .lines()
+            .map(|l| l.split(',').next().unwrap().parse::<u64>().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(offsets, vec![header_len, header_len + frame.len() as u64]);
+
+        let index = fs::read_to_string(segment_path(&prefix, 2, "idx")).unwrap();
+        assert_eq!(index.lines().count(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_recorder_handle() {
+        let dir =
+            std::env::temp_dir().join(format!("ntrip-recorder-handle-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let prefix = dir.join("VALDM");
+
+        let data = [station_frame(1), station_frame(2)].concat();
+        let caster = MockCaster::default()
+            .with_mount(
+                "VALDM",
+                MockMount::icy()
+                    .with_delay(Duration::from_millis(200))
+                    .with_data(&data)
+                    .then_stall(),
+            )
+            .start()
+            .await
+            .unwrap();
+
+        // Written to the header only
+        let tls = TlsSettings::default()
+            .with_root("/etc/ssl/caster.pem")
+            .with_pinned_key("sha256//AAAA")
+            .with_pinned_key("sha256//BBBB")
+            .with_server_name("caster.example.com");
+        let socket = SocketOptions::default()
+            .with_keepalive(Duration::from_secs(30))
+            .with_keepalive_probes(Duration::from_secs(10), 3)
+            .with_nodelay()
+            .with_user_timeout(Duration::from_secs(45));
+        let config = caster
+            .config()
+            .with_auth_scheme(AuthScheme::Basic)
+            .with_interface("eth0")
+            .with_tls_settings(tls)
+            .with_socket_options(socket);
+        let header = RecordingHeader::new(&config, "VALDM");
+
+        let mut client = NtripClient::new(caster.config(), NtripCredentials::default())
+            .await
+            .unwrap();
+        let mut handle = client.mount("VALDM").await.unwrap();
+
+        let recorder = Recorder::create(&prefix, header, RecorderOptions::default()).unwrap();
+        assert!(handle.record(recorder).await.is_none());
+
+        handle.next().await.unwrap();
+        handle.next().await.unwrap();
+
+        // Written by the writer thread, flushed once stopped
+        let recorder = handle.stop_recording().await.unwrap();
+        let recording = fs::read(recorder.segment_path()).unwrap();
+
+        let (header, offset) = RecordingHeader::parse(&recording).unwrap();
+        assert_eq!(header.config, config);
+        assert_eq!(&recording[offset..], &data[..]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
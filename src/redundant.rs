@@ -0,0 +1,407 @@
+//! Redundant sources
+//!
+//! [RedundantSources] mounts N equivalent sources (the same station through
+//! several casters, typically) with a [NtripMultiplexer] and delivers each
+//! message once. Whichever source delivers a message first wins, so the
+//! lowest latency source is naturally preferred and a silent source is
+//! transparently covered by the others.
+//!
+//! Observation messages are identified by message number, station ID and
+//! epoch time; other messages (and MSM split over several messages)
+//! by their content.
+
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap, VecDeque},
+    hash::{Hash, Hasher},
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
+};
+
+use futures::Stream;
+
+use crate::{
+    envelope::Envelope,
+    framer::{message_number, payload_bits},
+    latency::{epoch_time, EpochTime},
+    multiplex::{MuxEvent, MuxStream, NtripMultiplexer},
+};
+
+/// Identity of a message, for deduplication
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+struct MessageKey {
+    number: u16,
+    station: u16,
+    epoch: Option<EpochTime>,
+    /// Content digest, when the epoch does not identify the message
+    digest: Option<u64>,
+}
+
+impl MessageKey {
+    fn new(frame: &[u8]) -> Option<Self> {
+        let number = message_number(frame)?;
+        let station = payload_bits(frame, 12, 12)? as u16;
+        let epoch = epoch_time(frame);
+
+        // MSM multiple message bit, right after the 30 bit epoch time
+        let multiple = matches!(number, 1071..=1137) && payload_bits(frame, 54, 1)? == 1;
+
+        let digest = (epoch.is_none() || multiple).then(|| {
+            let mut hasher = DefaultHasher::new();
+            frame.hash(&mut hasher);
+            hasher.finish()
+        });
+
+        Some(Self {
+            number,
+            station,
+            epoch,
+            digest,
+        })
+    }
+}
+
+/// Remembers the messages delivered within a time window, and their source.
+///
+/// A source repeating a message (casters resend unchanged station, antenna,
+/// bias and ephemeris messages every few seconds) is never suppressed: only
+/// copies from the other sources are. Messages without epoch time are only
+/// identified by content, so their copies are expected within a short
+/// arrival skew rather than the whole window.
+#[derive(Clone, Debug)]
+pub struct Deduplicator {
+    window: Duration,
+    skew: Duration,
+    seen: HashMap<MessageKey, (Box<str>, Instant)>,
+    order: VecDeque<(Instant, MessageKey)>,
+}
+
+impl Deduplicator {
+    /// Builds a [Deduplicator] remembering messages for this long,
+    /// and messages without epoch time for 2 s
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            skew: Duration::from_secs(2).min(window),
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Copies and returns [Deduplicator] remembering messages
+    /// without epoch time for this long
+    pub fn with_skew(&self, skew: Duration) -> Self {
+        let mut s = self.clone();
+        s.skew = skew.min(self.window);
+        s
+    }
+
+    /// Returns true if this frame was not delivered by another source
+    /// within the window. Invalid frames are always accepted.
+    pub fn accept(&mut self, source: &str, frame: &[u8], at: Instant) -> bool {
+        while let Some((seen_at, key)) = self.order.front() {
+            if at.saturating_duration_since(*seen_at) <= self.window {
+                break;
+            }
+
+            // Entries refreshed since are queued again
+            if self.seen.get(key).is_some_and(|(_, at)| at == seen_at) {
+                self.seen.remove(key);
+            }
+            self.order.pop_front();
+        }
+
+        let Some(key) = MessageKey::new(frame) else {
+            return true;
+        };
+
+        let window = if key.digest.is_some() {
+            self.skew
+        } else {
+            self.window
+        };
+
+        match self.seen.get_mut(&key) {
+            Some((owner, seen_at))
+                if &**owner != source && at.saturating_duration_since(*seen_at) <= window =>
+            {
+                false
+            },
+            Some((owner, seen_at)) if &**owner == source => {
+                *seen_at = at;
+                self.order.push_back((at, key));
+                true
+            },
+            _ => {
+                self.seen.insert(key, (source.into(), at));
+                self.order.push_back((at, key));
+                true
+            },
+        }
+    }
+}
+
+/// Per source counters of a [RedundantStream]
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct SourceStats {
+    /// Source currently mounted
+    pub connected: bool,
+    /// Messages delivered first by this source
+    pub delivered: u64,
+    /// Messages already delivered by another source
+    pub duplicates: u64,
+    /// Last message received from this source
+    pub last_received: Option<Instant>,
+}
+
+/// Equivalent sources, see the [module](crate::redundant) documentation
+///
+/// ```no_run
+/// use futures::StreamExt;
+/// use ntrip_client::{
+///     MuxSource, NtripConfig, NtripCredentials, NtripMultiplexer, RedundantSources,
+/// };
+///
+/// # async fn run() {
+/// let creds = NtripCredentials::default().with_username("user").with_password("pass");
+/// let primary = NtripConfig::default().with_host("caster.example.com");
+/// let backup = NtripConfig::default().with_host("backup.example.com");
+///
+/// let mux = NtripMultiplexer::default()
+///     .with_source(MuxSource::new("primary", primary, creds.clone(), "VALDM"))
+///     .with_source(MuxSource::new("backup", backup, creds, "VALDM"));
+///
+/// let mut messages = RedundantSources::new(mux).start();
+///
+/// while let Some(envelope) = messages.next().await {
+///     println!("{:?}", envelope.message);
+/// }
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct RedundantSources {
+    mux: NtripMultiplexer,
+    window: Duration,
+    skew: Duration,
+}
+
+impl RedundantSources {
+    /// Deduplicates the sources of this [NtripMultiplexer]
+    pub fn new(mux: NtripMultiplexer) -> Self {
+        Self {
+            mux,
+            window: Duration::from_secs(30),
+            skew: Duration::from_secs(2),
+        }
+    }
+
+    /// Copies and returns [RedundantSources] remembering delivered messages
+    /// for this long: a source lagging behind by more than that
+    /// delivers duplicates
+    pub fn with_window(&self, window: Duration) -> Self {
+        let mut s = self.clone();
+        s.window = window;
+        s
+    }
+
+    /// Copies and returns [RedundantSources] expecting the copies of
+    /// messages without epoch time within this arrival skew,
+    /// see [Deduplicator::with_skew]
+    pub fn with_skew(&self, skew: Duration) -> Self {
+        let mut s = self.clone();
+        s.skew = skew;
+        s
+    }
+
+    /// Mounts all sources, see [NtripMultiplexer::start]
+    pub fn start(self) -> RedundantStream {
+        RedundantStream {
+            events: self.mux.start(),
+            dedup: Deduplicator::new(self.window).with_skew(self.skew),
+            sources: BTreeMap::new(),
+        }
+    }
+}
+
+/// Deduplicated [Stream] of [Envelope]s, see [RedundantSources::start]
+pub struct RedundantStream {
+    events: MuxStream,
+    dedup: Deduplicator,
+    sources: BTreeMap<Arc<str>, SourceStats>,
+}
+
+impl RedundantStream {
+    /// Returns the counters of each source
+    pub fn source_stats(&self) -> &BTreeMap<Arc<str>, SourceStats> {
+        &self.sources
+    }
+
+    /// Unmounts all sources, see [MuxStream::close]
+    pub fn close(&self) {
+        self.events.close();
+    }
+}
+
+impl Stream for RedundantStream {
+    type Item = Envelope;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let Some(event) = ready!(Pin::new(&mut self.events).poll_next(cx)) else {
+                return Poll::Ready(None);
+            };
+
+            let this = &mut *self;
+            if !this.sources.contains_key(event.source()) {
+                this.sources
+                    .insert(Arc::from(event.source()), SourceStats::default());
+            }
+            let stats = this.sources.get_mut(event.source()).unwrap();
+
+            match event {
+                MuxEvent::Connected { .. } => stats.connected = true,
+                MuxEvent::Disconnected { .. } => stats.connected = false,
+                MuxEvent::Message { source, envelope } => {
+                    stats.last_received = Some(envelope.instant);
+
+                    if this
+                        .dedup
+                        .accept(&source, &envelope.frame, envelope.instant)
+                    {
+                        stats.delivered += 1;
+                        return Poll::Ready(Some(*envelope));
+                    }
+
+                    stats.duplicates += 1;
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use rtcm_rs::{msg::Msg1004T, Message, MessageBuilder};
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::{
+        config::NtripCredentials,
+        mock::{station_frame, MockCaster, MockMount},
+        multiplex::MuxSource,
+    };
+
+    fn observation(station: u16, tow: u32) -> Vec<u8> {
+        MessageBuilder::new()
+            .build_message(&Message::Msg1004(Msg1004T {
+                reference_station_id: station,
+                gps_epoch_time_ms: tow,
+                ..Default::default()
+            }))
+            .unwrap()
+            .to_vec()
+    }
+
+    #[test]
+    fn test_deduplicator() {
+        let start = Instant::now();
+        let secs = |s: u64| start + Duration::from_secs(s);
+        let mut dedup = Deduplicator::new(Duration::from_secs(10));
+
+        assert!(dedup.accept("a", &observation(1, 1000), start));
+        assert!(!dedup.accept("b", &observation(1, 1000), start));
+        assert!(dedup.accept("b", &observation(2, 1000), start));
+        assert!(dedup.accept("b", &observation(1, 2000), start));
+
+        // Non observation messages: by content, within the arrival skew
+        assert!(dedup.accept("a", &station_frame(1), start));
+        assert!(!dedup.accept("b", &station_frame(1), start));
+        assert!(dedup.accept("b", &station_frame(2), start));
+        assert!(dedup.accept("b", &station_frame(1), secs(3)));
+
+        // Forgotten past the window
+        assert!(dedup.accept("b", &observation(1, 1000), secs(11)));
+    }
+
+    #[test]
+    fn test_deduplicator_repeats() {
+        let start = Instant::now();
+        let mut dedup = Deduplicator::new(Duration::from_secs(30));
+
+        // Unchanged 1005 resent by the caster
+        assert!(dedup.accept("a", &station_frame(1), start));
+        assert!(dedup.accept("a", &station_frame(1), start + Duration::from_secs(5)));
+        assert!(dedup.accept("a", &station_frame(1), start + Duration::from_secs(10)));
+
+        // Copy from another source, right after
+        let at = start + Duration::from_millis(10_500);
+        assert!(!dedup.accept("b", &station_frame(1), at));
+    }
+
+    #[tokio::test]
+    async fn test_redundant_sources() {
+        let data = [
+            observation(1, 1000),
+            observation(1, 2000),
+            observation(1, 3000),
+        ]
+        .concat();
+
+        // Primary delivers the first two epochs, then goes silent
+        let primary = MockCaster::default()
+            .with_mount(
+                "VALDM",
+                MockMount::icy()
+                    .with_data(&data[..2 * data.len() / 3])
+                    .then_stall(),
+            )
+            .start()
+            .await
+            .unwrap();
+
+        let backup = MockCaster::default()
+            .with_mount(
+                "VALDM",
+                MockMount::icy()
+                    .with_delay(Duration::from_millis(100))
+                    .with_data(&data)
+                    .then_stall(),
+            )
+            .start()
+            .await
+            .unwrap();
+
+        let creds = NtripCredentials::default();
+        let mux = NtripMultiplexer::default()
+            .with_source(MuxSource::new(
+                "primary",
+                primary.config(),
+                creds.clone(),
+                "VALDM",
+            ))
+            .with_source(MuxSource::new("backup", backup.config(), creds, "VALDM"));
+
+        let mut stream = RedundantSources::new(mux).start();
+
+        let mut epochs = Vec::new();
+        for _ in 0..3 {
+            let envelope = timeout(Duration::from_secs(5), stream.next())
+                .await
+                .unwrap()
+                .unwrap();
+
+            match envelope.message {
+                Message::Msg1004(m) => epochs.push(m.gps_epoch_time_ms),
+                m => panic!("unexpected message {:?}", m),
+            }
+        }
+        assert_eq!(epochs, vec![1000, 2000, 3000]);
+
+        let stats = stream.source_stats();
+        assert_eq!(stats["primary"].delivered, 2);
+        assert_eq!(stats["backup"].delivered, 1);
+        assert_eq!(stats["backup"].duplicates, 2);
+    }
+}
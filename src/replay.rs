@@ -0,0 +1,573 @@
+//! Replay of recorded RTCM files
+//!
+//! [Replay] reads a file produced by the [Recorder](crate::recorder::Recorder)
+//! (or any raw RTCM 3 capture) and streams it back through an [NtripHandle],
+//! so recorded corrections can be consumed exactly like a live mount.
+//!
+//! Recordings are read one segment at a time, while replaying.
+
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures::Stream;
+use rtcm_rs::MessageFrame;
+use tokio::{
+    select,
+    sync::mpsc::{unbounded_channel, UnboundedReceiver},
+    task::{coop::consume_budget, JoinHandle},
+    time::{sleep_until, Instant},
+};
+use tokio_util::sync::{CancellationToken, DropGuard};
+use tracing::{debug, error, warn};
+
+use crate::{
+    client::{record, NtripHandle, SharedState},
+    envelope::Envelope,
+    framer::{message_number, FramerStats, RtcmFramer},
+    recorder::{segment_path, RecordMode, RecordingHeader},
+    station::is_station_message,
+    NtripClientError,
+};
+
+/// Longest [RecordingHeader] read when opening a [Replay]
+const MAX_HEADER_LEN: u64 = 64 * 1024;
+
+/// Replay speed
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub enum Pacing {
+    /// Deliver frames as fast as possible
+    #[default]
+    AsFastAsPossible,
+    /// Deliver frames with their original timing
+    RealTime,
+    /// Deliver frames with their original timing scaled by this factor
+    /// (2.0 replays twice as fast). Factors that are not strictly positive
+    /// (including NaN) replay as fast as possible, with a warning.
+    Scaled(f64),
+}
+
+impl Pacing {
+    fn speed(&self) -> Option<f64> {
+        match self {
+            Self::AsFastAsPossible => None,
+            Self::RealTime => Some(1.0),
+            Self::Scaled(speed) if *speed > 0.0 => Some(*speed),
+            Self::Scaled(speed) => {
+                warn!(
+                    "Invalid replay speed {}, replaying as fast as possible",
+                    speed
+                );
+                None
+            },
+        }
+    }
+}
+
+/// RTCM frame read from a recording
+#[derive(Clone, PartialEq, Debug)]
+pub struct ReplayFrame {
+    /// Raw frame bytes, preamble and CRC included
+    pub data: Vec<u8>,
+    /// Receive time, when the recording has a timestamp index
+    pub received: Option<SystemTime>,
+}
+
+/// Segment file of a recording, and its timestamp index
+#[derive(Clone, PartialEq, Debug)]
+struct Segment {
+    path: PathBuf,
+    index: Option<PathBuf>,
+}
+
+impl Segment {
+    /// Segment with the timestamp index sidecar (`.idx` extension), when present
+    fn new(path: &Path) -> Self {
+        let index = path.with_extension("idx");
+
+        Self {
+            path: path.to_path_buf(),
+            index: index.exists().then_some(index),
+        }
+    }
+
+    /// Reads the segment data and its `(offset, receive time)` index entries
+    async fn load(&self) -> Result<(Vec<u8>, Vec<(u64, SystemTime)>), NtripClientError> {
+        let data = tokio::fs::read(&self.path).await?;
+
+        let index = match &self.index {
+            Some(index) => parse_index(&tokio::fs::read_to_string(index).await?)?,
+            None => Vec::new(),
+        };
+
+        Ok((data, index))
+    }
+}
+
+/// A recorded RTCM file, ready to replay
+#[derive(Clone, PartialEq, Debug)]
+pub struct Replay {
+    header: Option<RecordingHeader>,
+    segments: Vec<Segment>,
+}
+
+impl Replay {
+    /// Opens a recording. The timestamp index sidecar (same path, `.idx` extension)
+    /// is used when present.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, NtripClientError> {
+        Self::from_segments(vec![Segment::new(path.as_ref())])
+    }
+
+    /// Opens a recording with an explicit (optional) timestamp index
+    pub fn open_with_index(
+        path: impl AsRef<Path>,
+        index: Option<impl AsRef<Path>>,
+    ) -> Result<Self, NtripClientError> {
+        Self::from_segments(vec![Segment {
+            path: path.as_ref().to_path_buf(),
+            index: index.map(|i| i.as_ref().to_path_buf()),
+        }])
+    }
+
+    /// Opens all the segments written by a [Recorder](crate::recorder::Recorder)
+    /// with this path prefix, replayed in order
+    pub fn open_segments(prefix: impl AsRef<Path>) -> Result<Self, NtripClientError> {
+        let segments = (0..)
+            .map(|i| segment_path(prefix.as_ref(), i, "rtcm"))
+            .take_while(|path| path.exists())
+            .map(|path| Segment::new(&path))
+            .collect();
+
+        Self::from_segments(segments)
+    }
+
+    /// Reads the [RecordingHeader] of the first segment
+    fn from_segments(segments: Vec<Segment>) -> Result<Self, NtripClientError> {
+        let Some(first) = segments.first() else {
+            return Err(std::io::Error::from(std::io::ErrorKind::NotFound).into());
+        };
+
+        let mut start = Vec::new();
+        File::open(&first.path)?
+            .take(MAX_HEADER_LEN)
+            .read_to_end(&mut start)?;
+
+        Ok(Self {
+            header: RecordingHeader::parse(&start).map(|(header, _)| header),
+            segments,
+        })
+    }
+
+    /// Returns the [RecordingHeader], if the file has one
+    pub fn header(&self) -> Option<&RecordingHeader> {
+        self.header.as_ref()
+    }
+
+    /// Returns the segment files of this recording
+    pub fn segments(&self) -> impl Iterator<Item = &Path> {
+        self.segments.iter().map(|s| s.path.as_path())
+    }
+
+    /// Streams the recorded messages through an [NtripHandle]
+    pub fn into_handle(self, pacing: Pacing) -> NtripHandle {
+        let shared = SharedState::default();
+        let task_shared = shared.clone();
+
+        let mount: Arc<str> = self
+            .header
+            .as_ref()
+            .map(|h| Arc::from(h.mount.as_str()))
+            .unwrap_or_else(|| Arc::from(""));
+        let mut sequence = 0;
+
+        let (tx, rx) = unbounded_channel();
+        let cancel = CancellationToken::new();
+        let drain = CancellationToken::new();
+        let handle = spawn_paced(
+            self.segments,
+            pacing,
+            cancel.clone(),
+            drain.clone(),
+            move |frame, framer_stats| {
+                let instant = Instant::now().into_std();
+                let received = frame.received.unwrap_or_else(SystemTime::now);
+                *task_shared.framer_stats.lock().unwrap() = framer_stats;
+
+                record(
+                    &task_shared.recorder,
+                    RecordMode::Raw,
+                    &frame.data,
+                    received,
+                );
+                record(
+                    &task_shared.recorder,
+                    RecordMode::Frames,
+                    &frame.data,
+                    received,
+                );
+
+                // Statistics follow the replay pace
+                let number = message_number(&frame.data);
+                if let Some(number) = number {
+                    task_shared.stream_stats.lock().unwrap().record(
+                        number,
+                        frame.data.len(),
+                        instant,
+                    );
+                }
+
+                // Latency relative to the recorded receive time
+                if let Some(received) = frame.received {
+                    task_shared
+                        .latency
+                        .lock()
+                        .unwrap()
+                        .record(&frame.data, received);
+                }
+
+                let decoded = MessageFrame::new(&frame.data).ok();
+                {
+                    let mut summary = task_shared.summary.lock().unwrap();
+                    summary.bytes_received += frame.data.len() as u64;
+                    if decoded.is_none() {
+                        summary.bytes_discarded += frame.data.len() as u64;
+                    }
+                }
+
+                let Some(decoded) = decoded else {
+                    return true;
+                };
+
+                let message = decoded.get_message();
+                if number.is_some_and(is_station_message) {
+                    task_shared.update_station(&message);
+                }
+
+                let envelope = Envelope {
+                    message,
+                    frame: frame.data,
+                    mount: mount.clone(),
+                    sequence,
+                    received,
+                    instant,
+                };
+                sequence += 1;
+
+                if tx.send(envelope).is_err() {
+                    return false;
+                }
+
+                task_shared.summary.lock().unwrap().frames_delivered += 1;
+                true
+            },
+        );
+
+        NtripHandle::new(handle, rx, shared, cancel, drain)
+    }
+
+    /// Streams the recorded raw frames
+    pub fn into_frames(self, pacing: Pacing) -> ReplayFrames {
+        let (tx, rx) = unbounded_channel();
+        let cancel = CancellationToken::new();
+        let handle = spawn_paced(
+            self.segments,
+            pacing,
+            cancel.clone(),
+            CancellationToken::new(),
+            move |frame, _| tx.send(frame).is_ok(),
+        );
+
+        ReplayFrames {
+            _rx_handle: handle,
+            _cancel: cancel.drop_guard(),
+            frames_rx: rx,
+        }
+    }
+}
+
+/// [Stream] of [ReplayFrame]s, see [Replay::into_frames]
+pub struct ReplayFrames {
+    _rx_handle: JoinHandle<()>,
+    _cancel: DropGuard,
+    frames_rx: UnboundedReceiver<ReplayFrame>,
+}
+
+impl Stream for ReplayFrames {
+    type Item = ReplayFrame;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.frames_rx.poll_recv(cx)
+    }
+}
+
+/// Spawns a task reading `segments` one at a time, and handing their frames
+/// over to `deliver` according to [Pacing], with the [FramerStats] so far.
+/// `deliver` returns false once the receiver is gone.
+/// Stops when `cancel` is cancelled. Once `drain` is cancelled, the frames
+/// of the current segment are delivered without pacing, and no other segment is read.
+fn spawn_paced(
+    segments: Vec<Segment>,
+    pacing: Pacing,
+    cancel: CancellationToken,
+    drain: CancellationToken,
+    mut deliver: impl FnMut(ReplayFrame, FramerStats) -> bool + Send + 'static,
+) -> JoinHandle<()> {
+    let mut speed = pacing.speed();
+
+    tokio::task::spawn(async move {
+        // Frames may straddle segments
+        let mut framer = RtcmFramer::default();
+        let mut pushed = 0;
+
+        let start = Instant::now();
+        let mut first = None;
+
+        'segments: for segment in segments {
+            if cancel.is_cancelled() || drain.is_cancelled() {
+                break;
+            }
+
+            let (data, index) = match segment.load().await {
+                Ok(segment) => segment,
+                Err(e) => {
+                    error!("Replay of {} failed: {}", segment.path.display(), e);
+                    break;
+                },
+            };
+
+            if speed.is_some() && index.is_empty() {
+                warn!(
+                    "{} has no timestamp index, replaying it as fast as possible",
+                    segment.path.display()
+                );
+            }
+
+            let offset = RecordingHeader::parse(&data).map_or(0, |(_, len)| len);
+            let base = pushed;
+            framer.push(&data[offset..]);
+            pushed += (data.len() - offset) as u64;
+
+            while let Some(frame) = framer.next_frame() {
+                // A frame is available once its last byte has been received
+                let last = framer.stats().consumed_bytes() - 1;
+                let received = last.checked_sub(base).and_then(|last| {
+                    let last = last + offset as u64;
+                    match index.partition_point(|(o, _)| *o <= last) {
+                        0 => None,
+                        i => Some(index[i - 1].1),
+                    }
+                });
+
+                if drain.is_cancelled() {
+                    speed = None;
+                }
+
+                match (speed, received) {
+                    (Some(s), Some(received)) => {
+                        let first = *first.get_or_insert(received);
+                        let elapsed = received.duration_since(first).unwrap_or_default();
+                        select! {
+                            _ = sleep_until(start + elapsed.div_f64(s)) => {},
+                            _ = cancel.cancelled() => break 'segments,
+                            _ = drain.cancelled() => speed = None,
+                        }
+                    },
+                    // Yield now and then, so that cancellation is noticed
+                    _ => {
+                        consume_budget().await;
+                        if cancel.is_cancelled() {
+                            break 'segments;
+                        }
+                    },
+                }
+
+                let frame = ReplayFrame {
+                    data: frame,
+                    received,
+                };
+
+                if !deliver(frame, framer.stats()) {
+                    debug!("Replay receiver dropped");
+                    break 'segments;
+                }
+            }
+        }
+
+        if framer.buffered() > 0 || framer.stats().skipped_bytes > 0 {
+            debug!(
+                "Ignoring {} invalid and {} trailing bytes",
+                framer.stats().skipped_bytes,
+                framer.buffered()
+            );
+        }
+    })
+}
+
+/// Parses the `offset,length,seconds.nanoseconds` lines of an index sidecar
+fn parse_index(index: &str) -> Result<Vec<(u64, SystemTime)>, NtripClientError> {
+    let invalid = |line: &str| NtripClientError::InvalidIndex(line.to_string());
+
+    index
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|line| {
+            let mut parts = line.split(',');
+            let offset = parts
+                .next()
+                .and_then(|s| s.parse::<u64>().ok())
+                .ok_or_else(|| invalid(line))?;
+
+            let (secs, nanos) = parts
+                .nth(1)
+                .and_then(|s| s.split_once('.'))
+                .ok_or_else(|| invalid(line))?;
+
+            let secs = secs.parse::<u64>().map_err(|_| invalid(line))?;
+            let nanos = nanos.parse::<u32>().map_err(|_| invalid(line))?;
+
+            Ok((offset, UNIX_EPOCH + Duration::new(secs, nanos)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use futures::StreamExt;
+    use rtcm_rs::Message;
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::{
+        config::NtripConfig,
+        mock::station_frame as frame,
+        recorder::{Recorder, RecorderOptions},
+    };
+
+    /// Records these frames, `interval` apart
+    fn recording(name: &str, count: u16, interval: Duration, options: RecorderOptions) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("ntrip-replay-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let prefix = dir.join("VALDM");
+
+        let header = RecordingHeader::new(&NtripConfig::default(), "VALDM");
+        let mut recorder = Recorder::create(&prefix, header, options.with_index()).unwrap();
+
+        let t0 = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        for i in 0..count {
+            recorder.write(&frame(i), t0 + interval * i as u32).unwrap();
+        }
+        recorder.flush().unwrap();
+
+        prefix
+    }
+
+    #[tokio::test]
+    async fn test_replay_recording() {
+        // Two frames per segment
+        let header_len = RecordingHeader::new(&NtripConfig::default(), "VALDM")
+            .format(0, SystemTime::now())
+            .len();
+        let options = RecorderOptions::default()
+            .with_max_segment_size((header_len + 2 * frame(0).len()) as u64);
+        let prefix = recording("segments", 5, Duration::from_millis(10), options);
+
+        let replay = Replay::open_segments(&prefix).unwrap();
+        assert_eq!(replay.segments().count(), 3);
+
+        let header = replay.header().unwrap();
+        assert_eq!(header.mount, "VALDM");
+        assert_eq!(header.config, NtripConfig::default());
+
+        let frames = replay
+            .clone()
+            .into_frames(Pacing::AsFastAsPossible)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(frames.len(), 5);
+        assert_eq!(frames[4].data, frame(4));
+        assert_eq!(
+            frames[4].received,
+            Some(frames[0].received.unwrap() + Duration::from_millis(40))
+        );
+
+        let mut handle = replay.into_handle(Pacing::RealTime);
+        for i in 0..5 {
+            match handle.next().await {
+                Some(Message::Msg1005(m)) => assert_eq!(m.reference_station_id, i),
+                m => panic!("unexpected message {:?}", m),
+            }
+        }
+        assert!(handle.next().await.is_none());
+
+        fs::remove_dir_all(prefix.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay_close_gracefully() {
+        let prefix = recording(
+            "drain",
+            3,
+            Duration::from_secs(10),
+            RecorderOptions::default(),
+        );
+        let replay = Replay::open_segments(&prefix).unwrap();
+
+        let mut handle = replay.into_handle(Pacing::RealTime);
+        assert!(handle.next().await.is_some());
+
+        // Remaining frames delivered without waiting for their time
+        let summary = timeout(Duration::from_secs(1), handle.close_gracefully())
+            .await
+            .unwrap();
+        assert_eq!(summary.frames_delivered, 3);
+        assert_eq!(handle.count().await, 2);
+
+        fs::remove_dir_all(prefix.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay_raw_bytes() {
+        // Plain capture: no header, no index, garbage (and a false preamble) between frames
+        let mut data = b"ICY 200 OK\r\n\r\n".to_vec();
+        data.extend(frame(1));
+        data.extend([0xd3, 0x00, 0x42]);
+        data.extend(frame(2));
+
+        let path =
+            std::env::temp_dir().join(format!("ntrip-replay-raw-{}.rtcm", std::process::id()));
+        fs::write(&path, &data).unwrap();
+
+        let replay = Replay::open(&path).unwrap();
+        assert!(replay.header().is_none());
+
+        // Invalid factors: as fast as possible
+        assert_eq!(Pacing::Scaled(2.0).speed(), Some(2.0));
+        assert_eq!(Pacing::Scaled(0.0).speed(), None);
+        assert_eq!(Pacing::Scaled(f64::NAN).speed(), None);
+
+        let mut handle = replay.clone().into_handle(Pacing::Scaled(-1.0));
+        assert_eq!(handle.by_ref().count().await, 2);
+        assert_eq!(handle.framer_stats().frames, 2);
+        assert_eq!(handle.framer_stats().skipped_bytes, 14 + 3);
+
+        let frames = replay
+            .into_frames(Pacing::RealTime)
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].data, frame(2));
+        assert!(frames[1].received.is_none());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
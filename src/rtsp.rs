@@ -0,0 +1,494 @@
+//! RTSP/RTP transport (NTRIP 2.0)
+//!
+//! The mount is negotiated on an RTSP control connection (`SETUP`, `PLAY`),
+//! the caster then sends the RTCM stream as RTP packets over UDP. Payloads are
+//! reassembled into the byte stream the TCP transport provides, so frames are
+//! parsed (and recorded) by the same [NtripHandle] machinery.
+//! The session is kept alive with `GET_PARAMETER` and closed with `TEARDOWN`.
+
+use std::time::Duration;
+
+use http::HeaderValue;
+use tokio::{
+    io::{duplex, AsyncReadExt, AsyncWriteExt, DuplexStream},
+    net::{TcpStream, UdpSocket},
+    select,
+    time::{interval_at, Instant},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, warn};
+
+use crate::{
+    auth::{challenge_authorization, preemptive_authorization},
+    client::{NtripClient, NtripHandle},
+    config::{MountOptions, NtripConfig, NtripCredentials},
+    dial,
+    protocol::{encode_mount, host_header, user_agent, BodyDecoder, ResponseHead},
+    NtripClientError,
+};
+
+/// Keepalive period when the caster does not announce a session timeout
+const DEFAULT_KEEPALIVE: Duration = Duration::from_secs(30);
+
+/// Largest UDP datagram
+const MAX_PACKET: usize = 65536;
+
+/// Extracts the payload of RTP packets, dropping late and duplicated packets
+#[derive(Clone, Default, Debug)]
+pub struct RtpDepacketizer {
+    next_seq: Option<u16>,
+    lost: u64,
+}
+
+impl RtpDepacketizer {
+    /// Returns the payload of this RTP packet, or None if it is invalid or late
+    pub fn push<'a>(&mut self, packet: &'a [u8]) -> Option<&'a [u8]> {
+        if packet.len() < 12 || packet[0] >> 6 != 2 {
+            warn!("Ignoring invalid RTP packet ({} bytes)", packet.len());
+            return None;
+        }
+
+        let padding = packet[0] & 0x20 != 0;
+        let extension = packet[0] & 0x10 != 0;
+        let csrc_count = (packet[0] & 0x0f) as usize;
+        let seq = u16::from_be_bytes([packet[2], packet[3]]);
+
+        let mut start = 12 + 4 * csrc_count;
+
+        if extension {
+            let header = packet.get(start..start + 4)?;
+            start += 4 + 4 * u16::from_be_bytes([header[2], header[3]]) as usize;
+        }
+
+        let mut end = packet.len();
+
+        if padding {
+            end = end.checked_sub(*packet.last()? as usize)?;
+        }
+
+        if start > end {
+            warn!("Ignoring truncated RTP packet {}", seq);
+            return None;
+        }
+
+        if let Some(next) = self.next_seq {
+            let delta = seq.wrapping_sub(next);
+
+            if delta >= 0x8000 {
+                debug!("Dropping late RTP packet {}", seq);
+                return None;
+            }
+
+            if delta > 0 {
+                warn!("Lost {} RTP packets", delta);
+                self.lost += delta as u64;
+            }
+        }
+
+        self.next_seq = Some(seq.wrapping_add(1));
+
+        Some(&packet[start..end])
+    }
+
+    /// Returns the number of packets lost so far
+    pub fn lost(&self) -> u64 {
+        self.lost
+    }
+}
+
+/// RTSP control connection
+struct RtspControl {
+    sock: TcpStream,
+    buff: Vec<u8>,
+    url: String,
+    cseq: u32,
+    session: Option<String>,
+    authorization: Option<HeaderValue>,
+}
+
+impl RtspControl {
+    async fn request(
+        &mut self,
+        method: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<ResponseHead, NtripClientError> {
+        self.cseq += 1;
+
+        let mut request = format!("{} {} RTSP/1.0\r\n", method, self.url);
+        request.push_str(&format!("CSeq: {}\r\n", self.cseq));
+        request.push_str("Ntrip-Version: Ntrip/2.0\r\n");
+        request.push_str(&format!("User-Agent: {}\r\n", user_agent()));
+
+        if let Some(session) = &self.session {
+            request.push_str(&format!("Session: {}\r\n", session));
+        }
+
+        if let Some(authorization) = &self.authorization {
+            request.push_str(&format!("Authorization: {}\r\n", authorization.to_str()?));
+        }
+
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+
+        request.push_str("\r\n");
+
+        debug!("RTSP {} {} (CSeq {})", method, self.url, self.cseq);
+
+        self.sock.write_all(request.as_bytes()).await?;
+        self.sock.flush().await?;
+
+        self.read_response().await
+    }
+
+    async fn read_response(&mut self) -> Result<ResponseHead, NtripClientError> {
+        let response = ResponseHead::read(&mut self.sock, &mut self.buff).await?;
+
+        // Skip the body (e.g. GET_PARAMETER parameters)
+        let mut len = response
+            .header("Content-Length")
+            .and_then(|l| l.parse::<usize>().ok())
+            .unwrap_or_default();
+
+        while self.buff.len() < len {
+            len -= self.buff.len();
+            self.buff.clear();
+
+            if self.sock.read_buf(&mut self.buff).await? == 0 {
+                return Err(NtripClientError::Rtsp("control connection closed".into()));
+            }
+        }
+
+        self.buff.drain(..len);
+        Ok(response)
+    }
+
+    /// Checks for a `200 OK` response
+    fn expect_ok(response: ResponseHead) -> Result<ResponseHead, NtripClientError> {
+        if response.status == 200 {
+            Ok(response)
+        } else {
+            error!("NTRIP server returned error: {}", response.status_line);
+            Err(NtripClientError::ResponseError(response.status_line))
+        }
+    }
+}
+
+/// Splits a `Session` header into the session id and keepalive period
+fn parse_session(session: &str) -> (String, Duration) {
+    let mut params = session.split(';').map(str::trim);
+    let id = params.next().unwrap_or_default().to_string();
+
+    let keepalive = params
+        .find_map(|p| p.strip_prefix("timeout="))
+        .and_then(|t| t.parse::<u64>().ok())
+        .filter(|t| *t > 0)
+        .map(|t| Duration::from_millis(t * 500))
+        .unwrap_or(DEFAULT_KEEPALIVE);
+
+    (id, keepalive)
+}
+
+/// Returns the first `server_port` of a `Transport` header
+fn server_port(transport: &str) -> Option<u16> {
+    transport
+        .split(';')
+        .find_map(|p| p.trim().strip_prefix("server_port="))
+        .and_then(|p| p.split('-').next())
+        .and_then(|p| p.parse().ok())
+}
+
+/// Mounts over RTSP/RTP, see the [module](crate::rtsp) documentation
+pub(crate) async fn mount(
+    config: &NtripConfig,
+    creds: &NtripCredentials,
+    mount: &str,
+    cancel: CancellationToken,
+    options: MountOptions,
+) -> Result<NtripHandle, NtripClientError> {
+    if config.use_tls {
+        return Err(NtripClientError::Rtsp(
+            "TLS is not supported by the RTSP transport".into(),
+        ));
+    }
+
+    if config.effective_proxy().is_some() {
+        warn!("Proxy settings do not apply to the RTSP transport");
+    }
+
+    // RTP is received on the local address of the control connection
+    let sock = dial::connect(&config.host, config.port, config).await?;
+    let addr = sock.peer_addr()?;
+    let udp = dial::bind_udp(addr, sock.local_addr()?.ip(), config)?;
+    let transport = format!("RTP/GNSS;unicast;client_port={}", udp.local_addr()?.port());
+
+    let mut control = RtspControl {
+        sock,
+        buff: Vec::new(),
+        url: format!("rtsp://{}/{}", host_header(config), encode_mount(mount)),
+        cseq: 0,
+        session: None,
+        authorization: preemptive_authorization(config.auth, creds)?,
+    };
+
+    let mut response = control
+        .request("SETUP", &[("Transport", &transport)])
+        .await?;
+
+    if response.status == 401 {
+        let retry = challenge_authorization(
+            config.auth,
+            creds,
+            &response.challenges(),
+            "SETUP",
+            &control.url,
+        )?;
+
+        if let Some(retry) = retry.filter(|r| control.authorization.as_ref() != Some(r)) {
+            debug!("Retrying with challenge response");
+            control.authorization = Some(retry);
+            response = control
+                .request("SETUP", &[("Transport", &transport)])
+                .await?;
+        }
+    }
+
+    let response = RtspControl::expect_ok(response)?;
+
+    let (session, keepalive) = parse_session(
+        response
+            .header("Session")
+            .ok_or_else(|| NtripClientError::Rtsp("missing Session header".into()))?,
+    );
+    control.session = Some(session);
+
+    // Only accept packets from the caster
+    if let Some(port) = response.header("Transport").and_then(server_port) {
+        udp.connect((addr.ip(), port)).await?;
+    }
+
+    RtspControl::expect_ok(control.request("PLAY", &[]).await?)?;
+
+    let (reader, writer) = duplex(MAX_PACKET);
+    tokio::task::spawn(stream_rtp(control, udp, writer, keepalive, cancel.clone()));
+
+    Ok(NtripClient::spawn_listener(
+        mount,
+        Vec::new(),
+        BodyDecoder::Identity,
+        reader,
+        cancel,
+        options,
+    ))
+}
+
+/// Forwards RTP payloads to the listener and keeps the session alive
+async fn stream_rtp(
+    mut control: RtspControl,
+    udp: UdpSocket,
+    mut writer: DuplexStream,
+    keepalive: Duration,
+    cancel: CancellationToken,
+) {
+    let mut depacketizer = RtpDepacketizer::default();
+    let mut packet = vec![0; MAX_PACKET];
+    let mut keepalive = interval_at(Instant::now() + keepalive, keepalive);
+
+    loop {
+        select! {
+            n = udp.recv(&mut packet) => match n {
+                Ok(n) => {
+                    if let Some(payload) = depacketizer.push(&packet[..n]) {
+                        if writer.write_all(payload).await.is_err() {
+                            debug!("NTRIP listener closed");
+                            break;
+                        }
+                    }
+                },
+                Err(e) => {
+                    error!("RTP receive error: {}", e);
+                    break;
+                },
+            },
+            _ = keepalive.tick() => match control.request("GET_PARAMETER", &[]).await {
+                Ok(response) if response.status != 200 => {
+                    warn!("RTSP keepalive refused: {}", response.status_line);
+                },
+                Ok(_) => {},
+                Err(e) => {
+                    error!("RTSP keepalive error: {}", e);
+                    break;
+                },
+            },
+            _ = cancel.cancelled() => {
+                debug!("Exiting RTP loop on cancellation");
+                break;
+            },
+        }
+    }
+
+    if let Err(e) = control.request("TEARDOWN", &[]).await {
+        debug!("RTSP teardown failed: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use rtcm_rs::Message;
+    use tokio::{net::TcpListener, time::timeout};
+
+    use super::*;
+    use crate::{config::Transport, mock::read_request, mock::station_frame};
+
+    fn rtp_packet(seq: u16, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x80, 96];
+        packet.extend(seq.to_be_bytes());
+        packet.extend(1234u32.to_be_bytes());
+        packet.extend(0xcafeu32.to_be_bytes());
+        packet.extend(payload);
+        packet
+    }
+
+    #[test]
+    fn test_rtp_depacketizer() {
+        let mut depacketizer = RtpDepacketizer::default();
+
+        assert_eq!(
+            depacketizer.push(&rtp_packet(65535, b"ab")),
+            Some(&b"ab"[..])
+        );
+        assert_eq!(depacketizer.push(&rtp_packet(0, b"cd")), Some(&b"cd"[..]));
+
+        // Duplicate and late packets
+        assert_eq!(depacketizer.push(&rtp_packet(0, b"cd")), None);
+        assert_eq!(depacketizer.push(&rtp_packet(65535, b"ab")), None);
+
+        // Gap
+        assert_eq!(depacketizer.push(&rtp_packet(3, b"ef")), Some(&b"ef"[..]));
+        assert_eq!(depacketizer.lost(), 2);
+
+        // CSRC, header extension and padding
+        let mut packet = rtp_packet(4, &[]);
+        packet[0] = 0x80 | 0x20 | 0x10 | 1;
+        packet.extend([0; 4]);
+        packet.extend([0xbe, 0xde, 0, 1, 0, 0, 0, 0]);
+        packet.extend(b"gh");
+        packet.extend([0, 2]);
+        assert_eq!(depacketizer.push(&packet), Some(&b"gh"[..]));
+
+        assert_eq!(depacketizer.push(&[0x40; 12]), None);
+        assert_eq!(depacketizer.push(&[0x80; 4]), None);
+    }
+
+    #[test]
+    fn test_rtsp_headers() {
+        assert_eq!(
+            parse_session("12345678;timeout=60"),
+            ("12345678".to_string(), Duration::from_secs(30))
+        );
+        assert_eq!(parse_session("abc").1, DEFAULT_KEEPALIVE);
+        assert_eq!(
+            server_port("RTP/GNSS;unicast;client_port=5000;server_port=6000-6001"),
+            Some(6000)
+        );
+        assert_eq!(server_port("RTP/GNSS;unicast"), None);
+    }
+
+    #[tokio::test]
+    async fn test_rtsp_mount() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (methods_tx, mut methods_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        tokio::task::spawn(async move {
+            let (mut sock, _) = listener.accept().await.unwrap();
+            let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let mut client = None;
+
+            while let Some(request) = read_request(&mut sock).await.unwrap() {
+                let method = request.request_line.split(' ').next().unwrap().to_string();
+                let cseq = request.header("CSeq").unwrap().to_string();
+
+                let mut response = format!("RTSP/1.0 200 OK\r\nCSeq: {}\r\n", cseq);
+
+                if method == "SETUP" {
+                    let transport = request.header("Transport").unwrap();
+                    let port = transport.rsplit("client_port=").next().unwrap();
+                    client = Some(format!("127.0.0.1:{}", port));
+
+                    response.push_str("Session: 42;timeout=1\r\n");
+                    response.push_str(&format!(
+                        "Transport: {};server_port={}\r\n",
+                        transport,
+                        udp.local_addr().unwrap().port()
+                    ));
+                } else {
+                    assert_eq!(request.header("Session"), Some("42"));
+                }
+
+                sock.write_all(format!("{}\r\n", response).as_bytes())
+                    .await
+                    .unwrap();
+
+                if method == "PLAY" {
+                    // Frames split across packets, with a duplicate
+                    let client = client.as_ref().unwrap();
+                    let (first, second) = (station_frame(1), station_frame(2));
+                    let packets = [
+                        rtp_packet(10, &first[..5]),
+                        rtp_packet(11, &first[5..]),
+                        rtp_packet(11, &first[5..]),
+                        rtp_packet(12, &second),
+                    ];
+                    for packet in packets {
+                        udp.send_to(&packet, client).await.unwrap();
+                    }
+                }
+
+                methods_tx.send(method).unwrap();
+            }
+        });
+
+        let config = NtripConfig::default()
+            .with_host("127.0.0.1")
+            .with_port(addr.port())
+            .with_transport(Transport::Rtsp);
+
+        let mut client = NtripClient::new(config, NtripCredentials::default())
+            .await
+            .unwrap();
+        let mut handle = client.mount("VALDM").await.unwrap();
+
+        for station in [1, 2] {
+            match timeout(Duration::from_secs(5), handle.next())
+                .await
+                .unwrap()
+            {
+                Some(Message::Msg1005(m)) => assert_eq!(m.reference_station_id, station),
+                m => panic!("unexpected message {:?}", m),
+            }
+        }
+
+        for method in ["SETUP", "PLAY", "GET_PARAMETER"] {
+            let received = timeout(Duration::from_secs(5), methods_rx.recv())
+                .await
+                .unwrap();
+            assert_eq!(received.as_deref(), Some(method));
+        }
+
+        // Dropping the handle tears the session down
+        drop(handle);
+
+        loop {
+            let method = timeout(Duration::from_secs(5), methods_rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            if method == "TEARDOWN" {
+                break;
+            }
+            assert_eq!(method, "GET_PARAMETER");
+        }
+    }
+}
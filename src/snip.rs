@@ -1,4 +1,4 @@
-use std::str::FromStr;
+use std::{fmt, str::FromStr, time::Duration};
 
 use geoutils::Location;
 use isocountry::CountryCode;
@@ -204,6 +204,58 @@ impl MountInfo {
             location,
         })
     }
+
+    /// Returns the advertised message numbers, with their update interval
+    /// when specified (`1077(1)`: every second). Other entries are ignored.
+    pub fn message_intervals(&self) -> Vec<(u16, Option<Duration>)> {
+        self.messages
+            .iter()
+            .filter_map(|m| {
+                let (number, interval) = match m.split_once('(') {
+                    Some((number, rest)) => (number, rest.strip_suffix(')')),
+                    None => (m.as_str(), None),
+                };
+
+                let number = number.trim().parse::<u16>().ok()?;
+                let interval = interval
+                    .and_then(|i| i.trim().parse::<f64>().ok())
+                    .filter(|i| i.is_finite() && *i > 0.0)
+                    .map(Duration::from_secs_f64);
+
+                Some((number, interval))
+            })
+            .collect()
+    }
+}
+
+/// Formats [MountInfo] back into a sourcetable STR record,
+/// which [MountInfo::parse] accepts.
+impl fmt::Display for MountInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let constellations = self
+            .constellations
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>()
+            .join("+");
+
+        write!(
+            f,
+            "STR;{};{};{};{};;{};{};{};{:.2};{:.2}",
+            self.name,
+            self.details,
+            self.protocol,
+            self.messages.join(","),
+            constellations,
+            self.network,
+            self.country
+                .as_ref()
+                .map(|c| c.alpha3())
+                .unwrap_or_default(),
+            self.location.latitude(),
+            self.location.longitude(),
+        )
+    }
 }
 
 #[cfg(test)]
@@ -254,6 +306,20 @@ mod tests {
         assert!((server_info.location.longitude() - 16.50).abs() < 0.001);
     }
 
+    #[test]
+    fn test_mount_info_display() {
+        let info = "STR;VargaRTKhr;Is near: Zagreb, Zagreb;RTCM 3.2;1006(1),1033(1),1074(1);;GPS+GLO+GAL+BDS;SNIP;HRV;46.44;16.50;1;0;sNTRIP;none;B;N;0;";
+
+        let mount_info = MountInfo::parse(info).unwrap();
+        let record = mount_info.to_string();
+
+        assert_eq!(
+            record,
+            "STR;VargaRTKhr;Is near: Zagreb, Zagreb;RTCM 3.2;1006(1),1033(1),1074(1);;GPS+GLO+GAL+BDS;SNIP;HRV;46.44;16.50"
+        );
+        assert_eq!(MountInfo::parse(&record), Some(mount_info));
+    }
+
     #[test]
     fn test_parse_snip_info() {
         setup_logging();
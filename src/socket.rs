@@ -0,0 +1,249 @@
+//! TCP socket options for NTRIP connections
+//!
+//! [SocketOptions] tune the TCP sockets of the mount connections.
+//! Keepalive probes detect half-open connections at the OS level,
+//! typically when a cellular NAT gateway silently drops an idle-looking
+//! connection on a low rate mount.
+
+use std::{io, time::Duration};
+
+use socket2::{Socket, TcpKeepalive};
+
+/// TCP socket options of an [NtripConfig](crate::config::NtripConfig)
+#[derive(Clone, Default, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(clap::Args))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct SocketOptions {
+    /// Idle time (s) before sending TCP keepalive probes, the OS default when not set.
+    /// Keepalive is off unless one of the keepalive options is set.
+    #[cfg_attr(
+        feature = "clap",
+        clap(long = "ntrip-keepalive-idle", env = "NTRIP_KEEPALIVE_IDLE")
+    )]
+    pub keepalive_idle: Option<u64>,
+
+    /// Interval (s) between TCP keepalive probes
+    #[cfg_attr(
+        feature = "clap",
+        clap(long = "ntrip-keepalive-interval", env = "NTRIP_KEEPALIVE_INTERVAL")
+    )]
+    pub keepalive_interval: Option<u64>,
+
+    /// Unanswered TCP keepalive probes before the connection is dropped
+    #[cfg_attr(
+        feature = "clap",
+        clap(long = "ntrip-keepalive-count", env = "NTRIP_KEEPALIVE_COUNT")
+    )]
+    pub keepalive_count: Option<u32>,
+
+    /// Disable Nagle's algorithm (`TCP_NODELAY`)
+    #[cfg_attr(feature = "clap", clap(long = "ntrip-nodelay", env = "NTRIP_NODELAY"))]
+    pub nodelay: bool,
+
+    /// Receive buffer size (bytes)
+    #[cfg_attr(
+        feature = "clap",
+        clap(long = "ntrip-recv-buffer", env = "NTRIP_RECV_BUFFER")
+    )]
+    pub recv_buffer_size: Option<usize>,
+
+    /// Time (ms) sent data may remain unacknowledged before the connection
+    /// is dropped (`TCP_USER_TIMEOUT`, Linux only)
+    #[cfg_attr(
+        feature = "clap",
+        clap(long = "ntrip-user-timeout", env = "NTRIP_USER_TIMEOUT")
+    )]
+    pub user_timeout: Option<u64>,
+}
+
+impl SocketOptions {
+    /// Copies and returns [SocketOptions] sending keepalive probes after this idle time,
+    /// in whole seconds (truncated, at least 1 s)
+    pub fn with_keepalive(&self, idle: Duration) -> Self {
+        let mut s = self.clone();
+        s.keepalive_idle = Some(idle.as_secs().max(1));
+        s
+    }
+
+    /// Copies and returns [SocketOptions] sending `count` keepalive probes
+    /// at this interval, before dropping the connection.
+    /// The interval is in whole seconds (truncated, at least 1 s).
+    /// Keepalive is enabled, after the OS default idle time unless
+    /// [Self::with_keepalive] is used.
+    pub fn with_keepalive_probes(&self, interval: Duration, count: u32) -> Self {
+        let mut s = self.clone();
+        s.keepalive_interval = Some(interval.as_secs().max(1));
+        s.keepalive_count = Some(count);
+        s
+    }
+
+    /// Copies and returns [SocketOptions] with `TCP_NODELAY` set
+    pub fn with_nodelay(&self) -> Self {
+        let mut s = self.clone();
+        s.nodelay = true;
+        s
+    }
+
+    /// Copies and returns [SocketOptions] with this receive buffer size (bytes)
+    pub fn with_recv_buffer_size(&self, size: usize) -> Self {
+        let mut s = self.clone();
+        s.recv_buffer_size = Some(size);
+        s
+    }
+
+    /// Copies and returns [SocketOptions] with this `TCP_USER_TIMEOUT`,
+    /// in whole milliseconds
+    pub fn with_user_timeout(&self, timeout: Duration) -> Self {
+        let mut s = self.clone();
+        s.user_timeout = Some(u64::try_from(timeout.as_millis()).unwrap_or(u64::MAX));
+        s
+    }
+
+    /// Applies these options to a TCP socket, before it connects
+    pub(crate) fn apply(&self, socket: &Socket) -> io::Result<()> {
+        if self.keepalive_idle.is_some()
+            || self.keepalive_interval.is_some()
+            || self.keepalive_count.is_some()
+        {
+            let mut keepalive = TcpKeepalive::new();
+            if let Some(idle) = self.keepalive_idle {
+                keepalive = keepalive.with_time(Duration::from_secs(idle));
+            }
+            socket.set_tcp_keepalive(&self.keepalive_probes(keepalive))?;
+        }
+
+        if self.nodelay {
+            socket.set_tcp_nodelay(true)?;
+        }
+
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+
+        if let Some(timeout) = self.user_timeout {
+            set_user_timeout(socket, Duration::from_millis(timeout))?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(any(
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "fuchsia",
+        target_os = "ios",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "netbsd",
+        target_os = "windows",
+    ))]
+    fn keepalive_probes(&self, mut keepalive: TcpKeepalive) -> TcpKeepalive {
+        if let Some(interval) = self.keepalive_interval {
+            keepalive = keepalive.with_interval(Duration::from_secs(interval));
+        }
+
+        if let Some(count) = self.keepalive_count {
+            keepalive = keepalive.with_retries(count);
+        }
+
+        keepalive
+    }
+
+    #[cfg(not(any(
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "fuchsia",
+        target_os = "ios",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "netbsd",
+        target_os = "windows",
+    )))]
+    fn keepalive_probes(&self, keepalive: TcpKeepalive) -> TcpKeepalive {
+        if self.keepalive_interval.is_some() || self.keepalive_count.is_some() {
+            tracing::warn!("Keepalive probes settings are not supported on this platform");
+        }
+
+        keepalive
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+fn set_user_timeout(socket: &Socket, timeout: Duration) -> io::Result<()> {
+    socket.set_tcp_user_timeout(Some(timeout))
+}
+
+#[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+fn set_user_timeout(_: &Socket, _: Duration) -> io::Result<()> {
+    tracing::warn!("TCP user timeout is not supported on this platform");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use socket2::{Domain, Protocol, Type};
+
+    use super::*;
+
+    #[test]
+    fn test_socket_options() {
+        let options = SocketOptions::default()
+            .with_keepalive(Duration::from_secs(30))
+            .with_keepalive_probes(Duration::from_secs(10), 3)
+            .with_nodelay()
+            .with_recv_buffer_size(64 * 1024)
+            .with_user_timeout(Duration::from_secs(45));
+
+        // Whole seconds and milliseconds
+        let rounded = SocketOptions::default()
+            .with_keepalive(Duration::from_millis(500))
+            .with_keepalive_probes(Duration::from_millis(2500), 3)
+            .with_user_timeout(Duration::MAX);
+        assert_eq!(rounded.keepalive_idle, Some(1));
+        assert_eq!(rounded.keepalive_interval, Some(2));
+        assert_eq!(rounded.user_timeout, Some(u64::MAX));
+
+        let socket = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+        options.apply(&socket).unwrap();
+
+        assert!(socket.keepalive().unwrap());
+        assert!(socket.tcp_nodelay().unwrap());
+        assert!(socket.recv_buffer_size().unwrap() >= 64 * 1024);
+
+        #[cfg(target_os = "linux")]
+        {
+            assert_eq!(
+                socket.tcp_keepalive_time().unwrap(),
+                Duration::from_secs(30)
+            );
+            assert_eq!(
+                socket.tcp_keepalive_interval().unwrap(),
+                Duration::from_secs(10)
+            );
+            assert_eq!(socket.tcp_keepalive_retries().unwrap(), 3);
+            assert_eq!(
+                socket.tcp_user_timeout().unwrap(),
+                Some(Duration::from_secs(45))
+            );
+        }
+        // Probes only: keepalive on, after the OS default idle time
+        let options = SocketOptions::default().with_keepalive_probes(Duration::from_secs(5), 4);
+        let socket = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+        #[cfg(target_os = "linux")]
+        let idle = socket.tcp_keepalive_time().ok();
+        options.apply(&socket).unwrap();
+
+        assert!(socket.keepalive().unwrap());
+
+        #[cfg(target_os = "linux")]
+        {
+            assert_eq!(socket.tcp_keepalive_time().ok(), idle);
+            assert_eq!(
+                socket.tcp_keepalive_interval().unwrap(),
+                Duration::from_secs(5)
+            );
+            assert_eq!(socket.tcp_keepalive_retries().unwrap(), 4);
+        }
+    }
+}
@@ -0,0 +1,247 @@
+//! Reference station description
+//!
+//! [ReferenceStation] gathers what a mount says about its reference station:
+//! the antenna reference point (RTCM 1005 / 1006), much more precise than the
+//! sourcetable location, and the antenna and receiver descriptors
+//! (RTCM 1007 / 1008 / 1033).
+
+use geoutils::Location;
+use rtcm_rs::Message;
+
+use crate::snip::MountInfo;
+
+/// WGS84 semi-major axis (m)
+const WGS84_A: f64 = 6_378_137.0;
+
+/// WGS84 flattening
+const WGS84_F: f64 = 1.0 / 298.257_223_563;
+
+/// Returns true for the message numbers describing the reference station
+pub fn is_station_message(number: u16) -> bool {
+    matches!(number, 1005..=1008 | 1033)
+}
+
+/// Converts WGS84 ECEF coordinates (m) to geodetic
+/// latitude, longitude (degrees) and ellipsoidal height (m)
+pub fn ecef_to_geodetic(ecef: [f64; 3]) -> (f64, f64, f64) {
+    let [x, y, z] = ecef;
+    let e2 = WGS84_F * (2.0 - WGS84_F);
+    let p = x.hypot(y);
+    let lon = y.atan2(x);
+
+    // Poles: the iteration below divides by cos(lat)
+    if p < 1e-3 {
+        let b = WGS84_A * (1.0 - WGS84_F);
+        return (90.0_f64.copysign(z), lon.to_degrees(), z.abs() - b);
+    }
+
+    let mut lat = z.atan2(p * (1.0 - e2));
+    let mut height = 0.0;
+
+    for _ in 0..10 {
+        let n = WGS84_A / (1.0 - e2 * lat.sin().powi(2)).sqrt();
+        height = p / lat.cos() - n;
+
+        let next = z.atan2(p * (1.0 - e2 * n / (n + height)));
+        let converged = (next - lat).abs() < 1e-12;
+        lat = next;
+
+        if converged {
+            break;
+        }
+    }
+
+    (lat.to_degrees(), lon.to_degrees(), height)
+}
+
+/// Antenna reference point, from RTCM 1005 / 1006
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StationPosition {
+    /// WGS84 ECEF coordinates (m)
+    pub ecef: [f64; 3],
+    /// Antenna height above the marker (m), 1006 only
+    pub antenna_height: Option<f64>,
+    /// Geodetic location
+    pub location: Location,
+    /// Ellipsoidal height (m)
+    pub altitude: f64,
+}
+
+impl StationPosition {
+    /// Builds a [StationPosition] from WGS84 ECEF coordinates (m)
+    pub fn from_ecef(ecef: [f64; 3], antenna_height: Option<f64>) -> Self {
+        let (lat, lon, altitude) = ecef_to_geodetic(ecef);
+
+        Self {
+            ecef,
+            antenna_height,
+            location: Location::new(lat, lon),
+            altitude,
+        }
+    }
+}
+
+/// Antenna and receiver descriptors, from RTCM 1007 / 1008 / 1033
+#[derive(Clone, Default, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AntennaInfo {
+    /// Antenna descriptor (IGS antenna type and radome)
+    pub descriptor: String,
+    /// Antenna setup ID, 0 when the site setup is unknown
+    pub setup_id: u8,
+    /// Antenna serial number (1008 / 1033 only)
+    pub serial_number: Option<String>,
+    /// Receiver type descriptor (1033 only)
+    pub receiver_type: Option<String>,
+    /// Receiver firmware version (1033 only)
+    pub receiver_firmware: Option<String>,
+    /// Receiver serial number (1033 only)
+    pub receiver_serial_number: Option<String>,
+}
+
+/// Reference station of a mount, see the [module](crate::station) documentation
+#[derive(Clone, Default, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReferenceStation {
+    /// Station ID of the latest station message
+    pub station_id: u16,
+    /// Antenna reference point, once 1005 or 1006 was received
+    pub position: Option<StationPosition>,
+    /// Antenna description, once 1007, 1008 or 1033 was received
+    pub antenna: Option<AntennaInfo>,
+}
+
+impl ReferenceStation {
+    /// Updates from this message. Returns false if it does not describe the station.
+    pub fn update(&mut self, message: &Message) -> bool {
+        match message {
+            Message::Msg1005(m) => {
+                self.station_id = m.reference_station_id;
+                self.position = Some(StationPosition::from_ecef(
+                    [
+                        m.antenna_ref_point_ecef_x_m,
+                        m.antenna_ref_point_ecef_y_m,
+                        m.antenna_ref_point_ecef_z_m,
+                    ],
+                    None,
+                ));
+            },
+            Message::Msg1006(m) => {
+                self.station_id = m.reference_station_id;
+                self.position = Some(StationPosition::from_ecef(
+                    [
+                        m.antenna_ref_point_ecef_x_m,
+                        m.antenna_ref_point_ecef_y_m,
+                        m.antenna_ref_point_ecef_z_m,
+                    ],
+                    Some(m.antenna_height_m),
+                ));
+            },
+            Message::Msg1007(m) => {
+                self.station_id = m.reference_station_id;
+                let antenna = self.antenna.get_or_insert_with(Default::default);
+                antenna.descriptor = m.antenna_descriptor_str.chars().collect();
+                antenna.setup_id = m.antenna_setup_id;
+            },
+            Message::Msg1008(m) => {
+                self.station_id = m.reference_station_id;
+                let antenna = self.antenna.get_or_insert_with(Default::default);
+                antenna.descriptor = m.antenna_descriptor_str.chars().collect();
+                antenna.setup_id = m.antenna_setup_id;
+                antenna.serial_number = Some(m.antenna_serial_number_str.chars().collect());
+            },
+            Message::Msg1033(m) => {
+                self.station_id = m.reference_station_id;
+                self.antenna = Some(AntennaInfo {
+                    descriptor: m.antenna_descriptor_str.chars().collect(),
+                    setup_id: m.antenna_setup_id,
+                    serial_number: Some(m.antenna_serial_number_str.chars().collect()),
+                    receiver_type: Some(m.receiver_type_descriptor_str.chars().collect()),
+                    receiver_firmware: Some(m.receiver_firmware_version_str.chars().collect()),
+                    receiver_serial_number: Some(m.receiver_serial_number_str.chars().collect()),
+                });
+            },
+            _ => return false,
+        }
+
+        true
+    }
+
+    /// Returns the distance (m) between the antenna reference point and
+    /// the [MountInfo] location, when it exceeds this threshold (m)
+    pub fn location_mismatch(&self, info: &MountInfo, threshold: f64) -> Option<f64> {
+        let position = self.position.as_ref()?;
+        let distance = position
+            .location
+            .haversine_distance_to(&info.location)
+            .meters();
+
+        (distance > threshold).then_some(distance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rtcm_rs::msg::{Msg1006T, Msg1033T};
+
+    #[test]
+    fn test_ecef_to_geodetic() {
+        // Round trip from geodetic coordinates
+        let (lat, lon, height) = (43.5607_f64, 1.4809_f64, 207.0);
+        let e2 = WGS84_F * (2.0 - WGS84_F);
+        let (phi, lambda) = (lat.to_radians(), lon.to_radians());
+        let n = WGS84_A / (1.0 - e2 * phi.sin().powi(2)).sqrt();
+        let ecef = [
+            (n + height) * phi.cos() * lambda.cos(),
+            (n + height) * phi.cos() * lambda.sin(),
+            (n * (1.0 - e2) + height) * phi.sin(),
+        ];
+
+        let (lat2, lon2, height2) = ecef_to_geodetic(ecef);
+        assert!((lat2 - lat).abs() < 1e-9, "{}", lat2);
+        assert!((lon2 - lon).abs() < 1e-9, "{}", lon2);
+        assert!((height2 - height).abs() < 1e-4, "{}", height2);
+
+        let (lat, _, height) = ecef_to_geodetic([0.0, 0.0, -6_356_752.314_245]);
+        assert_eq!(lat, -90.0);
+        assert!(height.abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_reference_station() {
+        let mut station = ReferenceStation::default();
+
+        assert!(station.update(&Message::Msg1006(Msg1006T {
+            reference_station_id: 12,
+            antenna_ref_point_ecef_x_m: 4627851.810,
+            antenna_ref_point_ecef_y_m: 119640.085,
+            antenna_ref_point_ecef_z_m: 4372993.558,
+            antenna_height_m: 1.5,
+            ..Default::default()
+        })));
+
+        assert!(station.update(&Message::Msg1033(Msg1033T {
+            reference_station_id: 12,
+            antenna_descriptor_str: "TRM59800.00     NONE".into(),
+            receiver_type_descriptor_str: "SEPT POLARX5".into(),
+            ..Default::default()
+        })));
+
+        assert_eq!(station.station_id, 12);
+        assert_eq!(station.position.unwrap().antenna_height, Some(1.5));
+
+        let antenna = station.antenna.as_ref().unwrap();
+        assert_eq!(antenna.descriptor, "TRM59800.00     NONE");
+        assert_eq!(antenna.receiver_type.as_deref(), Some("SEPT POLARX5"));
+
+        // Sourcetable location with two decimals: within 1 km
+        let info = MountInfo::parse(
+            "STR;TLSE;Toulouse;RTCM 3.2;1006(10),1033(10);2;GPS;SNIP;FRA;43.56;1.48",
+        )
+        .unwrap();
+        assert_eq!(station.location_mismatch(&info, 1000.0), None);
+        assert!(station.location_mismatch(&info, 10.0).unwrap() > 10.0);
+    }
+}
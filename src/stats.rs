@@ -0,0 +1,326 @@
+//! Stream statistics
+//!
+//! [StreamStats] counts the frames delivered by a mount, per RTCM message
+//! number: observed intervals, inter-arrival jitter and gaps. It can be
+//! checked against the rates advertised in the sourcetable
+//! (see [MountInfo::message_intervals]) with [StreamStats::compare].
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::snip::MountInfo;
+
+/// An interval this many times longer than the mean is counted as a gap
+const GAP_FACTOR: f64 = 2.0;
+
+/// Statistics of a single RTCM message number
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MessageStats {
+    /// Frames received
+    pub count: u64,
+    /// Bytes received (whole frames)
+    pub bytes: u64,
+    /// First arrival
+    pub first: Instant,
+    /// Last arrival
+    pub last: Instant,
+    /// Intervals longer than twice the mean interval
+    pub gaps: u64,
+    /// Longest interval between two arrivals
+    pub max_interval: Duration,
+    /// Running mean of the intervals, in seconds
+    mean: f64,
+    /// Running sum of squared deviations of the intervals
+    m2: f64,
+}
+
+impl MessageStats {
+    fn new(bytes: usize, at: Instant) -> Self {
+        Self {
+            count: 1,
+            bytes: bytes as u64,
+            first: at,
+            last: at,
+            gaps: 0,
+            max_interval: Duration::ZERO,
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
+
+    fn record(&mut self, bytes: usize, at: Instant) {
+        let interval = at.saturating_duration_since(self.last);
+        let secs = interval.as_secs_f64();
+
+        // A gap is judged against the intervals seen so far
+        if self.count > 2 && secs > GAP_FACTOR * self.mean {
+            self.gaps += 1;
+        }
+
+        // Welford's online variance
+        let n = self.count as f64;
+        let delta = secs - self.mean;
+        self.mean += delta / n;
+        self.m2 += delta * (secs - self.mean);
+
+        self.count += 1;
+        self.bytes += bytes as u64;
+        self.last = at;
+        self.max_interval = self.max_interval.max(interval);
+    }
+
+    /// Returns the mean interval between two arrivals,
+    /// None until two frames were received
+    pub fn interval(&self) -> Option<Duration> {
+        (self.count > 1).then(|| Duration::from_secs_f64(self.mean))
+    }
+
+    /// Returns the observed rate, in messages per second
+    pub fn rate(&self) -> Option<f64> {
+        self.interval()
+            .filter(|i| !i.is_zero())
+            .map(|i| 1.0 / i.as_secs_f64())
+    }
+
+    /// Returns the inter-arrival jitter (standard deviation of the intervals),
+    /// None until three frames were received
+    pub fn jitter(&self) -> Option<Duration> {
+        (self.count > 2)
+            .then(|| Duration::from_secs_f64((self.m2 / (self.count - 2) as f64).sqrt()))
+    }
+}
+
+/// Statistics of a mounted stream, see the [module](crate::stats) documentation
+#[derive(Clone, PartialEq, Debug)]
+pub struct StreamStats {
+    /// Start of the collection
+    pub started: Instant,
+    /// Last frame arrival
+    pub updated: Instant,
+    /// Frames received
+    pub frames: u64,
+    /// Bytes received (whole frames)
+    pub bytes: u64,
+    /// Per message number statistics
+    pub messages: BTreeMap<u16, MessageStats>,
+}
+
+impl Default for StreamStats {
+    fn default() -> Self {
+        Self::new(Instant::now())
+    }
+}
+
+impl StreamStats {
+    /// Starts collecting at this instant
+    pub fn new(started: Instant) -> Self {
+        Self {
+            started,
+            updated: started,
+            frames: 0,
+            bytes: 0,
+            messages: BTreeMap::new(),
+        }
+    }
+
+    /// Accounts for a frame of this message number and size, received at this instant
+    pub fn record(&mut self, message: u16, bytes: usize, at: Instant) {
+        self.frames += 1;
+        self.bytes += bytes as u64;
+        self.updated = self.updated.max(at);
+
+        self.messages
+            .entry(message)
+            .and_modify(|m| m.record(bytes, at))
+            .or_insert_with(|| MessageStats::new(bytes, at));
+    }
+
+    /// Returns the time elapsed from the start to the last frame
+    pub fn elapsed(&self) -> Duration {
+        self.updated.saturating_duration_since(self.started)
+    }
+
+    /// Returns the mean throughput, in bytes per second
+    pub fn bytes_per_sec(&self) -> Option<f64> {
+        let elapsed = self.elapsed().as_secs_f64();
+        (elapsed > 0.0).then(|| self.bytes as f64 / elapsed)
+    }
+
+    /// Returns the [MessageStats] of this message number
+    pub fn message(&self, message: u16) -> Option<&MessageStats> {
+        self.messages.get(&message)
+    }
+
+    /// Compares the observed intervals to the ones advertised by this [MountInfo].
+    /// Intervals differing by more than `tolerance` (relative, e.g. `0.2`)
+    /// are reported [RateStatus::Slower] or [RateStatus::Faster].
+    pub fn compare(&self, info: &MountInfo, tolerance: f64) -> Vec<RateComparison> {
+        let advertised = info.message_intervals();
+
+        let mut report = advertised
+            .iter()
+            .map(|(message, interval)| {
+                RateComparison::new(*message, *interval, self.message(*message), tolerance)
+            })
+            .collect::<Vec<_>>();
+
+        report.extend(
+            self.messages
+                .iter()
+                .filter(|(n, _)| !advertised.iter().any(|(m, _)| m == *n))
+                .map(|(n, stats)| RateComparison {
+                    message: *n,
+                    advertised: None,
+                    observed: stats.interval(),
+                    count: stats.count,
+                    status: RateStatus::Unadvertised,
+                }),
+        );
+
+        report
+    }
+}
+
+/// Advertised versus observed interval of a message number
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RateComparison {
+    /// RTCM message number
+    pub message: u16,
+    /// Interval advertised by the sourcetable
+    pub advertised: Option<Duration>,
+    /// Observed mean interval
+    pub observed: Option<Duration>,
+    /// Frames received
+    pub count: u64,
+    /// Outcome
+    pub status: RateStatus,
+}
+
+impl RateComparison {
+    fn new(
+        message: u16,
+        advertised: Option<Duration>,
+        stats: Option<&MessageStats>,
+        tolerance: f64,
+    ) -> Self {
+        let observed = stats.and_then(|s| s.interval());
+
+        let status = match (stats, advertised, observed) {
+            (None, _, _) => RateStatus::Missing,
+            (_, None, _) | (_, _, None) => RateStatus::Unknown,
+            (_, Some(advertised), Some(observed)) => {
+                let ratio = observed.as_secs_f64() / advertised.as_secs_f64();
+
+                if ratio > 1.0 + tolerance {
+                    RateStatus::Slower
+                } else if ratio < 1.0 - tolerance {
+                    RateStatus::Faster
+                } else {
+                    RateStatus::Ok
+                }
+            },
+        };
+
+        Self {
+            message,
+            advertised,
+            observed,
+            count: stats.map(|s| s.count).unwrap_or_default(),
+            status,
+        }
+    }
+}
+
+impl fmt::Display for RateComparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = |d: Option<Duration>| {
+            d.map(|d| format!("{:.3}s", d.as_secs_f64()))
+                .unwrap_or_else(|| "-".to_string())
+        };
+
+        write!(
+            f,
+            "{}: advertised {}, observed {} ({} frames) {:?}",
+            self.message,
+            secs(self.advertised),
+            secs(self.observed),
+            self.count,
+            self.status
+        )
+    }
+}
+
+/// [RateComparison] outcome
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RateStatus {
+    /// Observed interval matches the advertised one
+    Ok,
+    /// Received less often than advertised
+    Slower,
+    /// Received more often than advertised
+    Faster,
+    /// Advertised, never received
+    Missing,
+    /// Received, not advertised
+    Unadvertised,
+    /// No advertised interval, or not enough frames to tell
+    Unknown,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_stats() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+
+        let mut stats = StreamStats::new(start);
+
+        // 1077 every second, missing two epochs
+        for s in [0, 1, 2, 3, 6, 7] {
+            stats.record(1077, 100, at(s * 1000));
+        }
+        // 1006 every 10 seconds
+        stats.record(1006, 27, at(0));
+        stats.record(1006, 27, at(10_000));
+        // 1230 is not advertised
+        stats.record(1230, 12, at(500));
+
+        assert_eq!(stats.frames, 9);
+        assert_eq!(stats.elapsed(), Duration::from_secs(10));
+        assert_eq!(stats.bytes_per_sec(), Some(66.6));
+
+        let msm = stats.message(1077).unwrap();
+        assert_eq!(msm.count, 6);
+        assert_eq!(msm.gaps, 1);
+        assert_eq!(msm.max_interval, Duration::from_secs(3));
+        assert!((msm.interval().unwrap().as_secs_f64() - 1.4).abs() < 1e-6);
+        assert!(msm.jitter().unwrap() > Duration::from_millis(800));
+
+        let info = MountInfo::parse(
+            "STR;VALDM;Valence;RTCM 3.2;1006(10),1077(1),1019(60),MSM;2;GPS;SNIP;FRA;44.93;4.89",
+        )
+        .unwrap();
+
+        let report = stats
+            .compare(&info, 0.1)
+            .iter()
+            .map(|c| (c.message, c.status))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            report,
+            vec![
+                (1006, RateStatus::Ok),
+                (1077, RateStatus::Slower),
+                (1019, RateStatus::Missing),
+                (1230, RateStatus::Unadvertised),
+            ]
+        );
+    }
+}
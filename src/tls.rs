@@ -0,0 +1,326 @@
+//! TLS settings for NTRIP connections
+//!
+//! [TlsSettings] builds the [rustls::ClientConfig] used by both the sourcetable
+//! request and the mount connection.
+
+use std::{path::PathBuf, sync::Arc};
+
+use base64::{engine::general_purpose, Engine as _};
+use rustls::{
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        WebPkiServerVerifier,
+    },
+    crypto::{ring, CryptoProvider},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use sha2::{Digest, Sha256};
+use tokio_rustls::TlsConnector;
+use tracing::{debug, warn};
+
+use crate::NtripClientError;
+
+/// TLS settings of an [NtripConfig](crate::config::NtripConfig)
+#[derive(Clone, Default, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(clap::Args))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct TlsSettings {
+    /// Additional trusted root certificates (PEM files)
+    #[cfg_attr(feature = "clap", clap(long = "ntrip-tls-ca", env = "NTRIP_TLS_CA"))]
+    pub extra_roots: Vec<PathBuf>,
+
+    /// Trust the root certificates of the operating system
+    #[cfg_attr(
+        feature = "clap",
+        clap(long = "ntrip-tls-native-roots", env = "NTRIP_TLS_NATIVE_ROOTS")
+    )]
+    pub native_roots: bool,
+
+    /// Do not trust the built-in (Mozilla) root certificates
+    #[cfg_attr(
+        feature = "clap",
+        clap(
+            long = "ntrip-tls-no-builtin-roots",
+            env = "NTRIP_TLS_NO_BUILTIN_ROOTS"
+        )
+    )]
+    pub no_builtin_roots: bool,
+
+    /// Client certificate chain (PEM file), for mutual TLS
+    #[cfg_attr(
+        feature = "clap",
+        clap(long = "ntrip-tls-cert", env = "NTRIP_TLS_CERT")
+    )]
+    pub client_cert: Option<PathBuf>,
+
+    /// Client private key (PEM file), for mutual TLS
+    #[cfg_attr(feature = "clap", clap(long = "ntrip-tls-key", env = "NTRIP_TLS_KEY"))]
+    pub client_key: Option<PathBuf>,
+
+    /// Accepted server public keys: base64 SHA-256 of the certificate SPKI,
+    /// optionally prefixed with "sha256//"
+    #[cfg_attr(feature = "clap", clap(long = "ntrip-tls-pin", env = "NTRIP_TLS_PIN"))]
+    pub pinned_keys: Vec<String>,
+
+    /// Server name to present (SNI) and verify, instead of the host name
+    #[cfg_attr(
+        feature = "clap",
+        clap(long = "ntrip-tls-server-name", env = "NTRIP_TLS_SERVER_NAME")
+    )]
+    pub server_name: Option<String>,
+}
+
+impl TlsSettings {
+    /// Copies and returns [TlsSettings] trusting an additional root certificate (PEM file)
+    pub fn with_root(&self, pem: impl Into<PathBuf>) -> Self {
+        let mut s = self.clone();
+        s.extra_roots.push(pem.into());
+        s
+    }
+
+    /// Copies and returns [TlsSettings] trusting the operating system roots
+    pub fn with_native_roots(&self) -> Self {
+        let mut s = self.clone();
+        s.native_roots = true;
+        s
+    }
+
+    /// Copies and returns [TlsSettings] without the built-in roots
+    pub fn without_builtin_roots(&self) -> Self {
+        let mut s = self.clone();
+        s.no_builtin_roots = true;
+        s
+    }
+
+    /// Copies and returns [TlsSettings] authenticating with a client certificate
+    pub fn with_client_auth(&self, cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        let mut s = self.clone();
+        s.client_cert = Some(cert.into());
+        s.client_key = Some(key.into());
+        s
+    }
+
+    /// Copies and returns [TlsSettings] with an additional pinned public key
+    pub fn with_pinned_key(&self, spki_sha256: &str) -> Self {
+        let mut s = self.clone();
+        s.pinned_keys.push(spki_sha256.to_string());
+        s
+    }
+
+    /// Copies and returns [TlsSettings] with a server name (SNI) override
+    pub fn with_server_name(&self, name: &str) -> Self {
+        let mut s = self.clone();
+        s.server_name = Some(name.to_string());
+        s
+    }
+
+    /// Returns the server name to present for this host
+    pub fn server_name(&self, host: &str) -> Result<ServerName<'static>, NtripClientError> {
+        let name = self.server_name.as_deref().unwrap_or(host);
+        let name = name.trim_start_matches('[').trim_end_matches(']');
+        Ok(ServerName::try_from(name.to_string())?)
+    }
+
+    /// Builds the [ClientConfig] described by these settings
+    pub fn client_config(&self) -> Result<ClientConfig, NtripClientError> {
+        let provider = Arc::new(ring::default_provider());
+
+        let mut roots = RootCertStore::empty();
+
+        if !self.no_builtin_roots {
+            roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        }
+
+        if self.native_roots {
+            let native = rustls_native_certs::load_native_certs();
+            for e in native.errors {
+                warn!("Failed to load native root certificate: {}", e);
+            }
+            let (added, ignored) = roots.add_parsable_certificates(native.certs);
+            debug!("Loaded {} native roots ({} ignored)", added, ignored);
+        }
+
+        for path in &self.extra_roots {
+            for cert in CertificateDer::pem_file_iter(path).map_err(|e| pem_error(path, e))? {
+                roots.add(cert.map_err(|e| pem_error(path, e))?)?;
+            }
+        }
+
+        if roots.is_empty() {
+            return Err(NtripClientError::Tls("no trusted root certificates".into()));
+        }
+
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+
+        let builder = if self.pinned_keys.is_empty() {
+            builder.with_root_certificates(roots)
+        } else {
+            let verifier = PinnedVerifier::new(roots, &self.pinned_keys, provider)?;
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(verifier))
+        };
+
+        let config = match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => {
+                let chain = CertificateDer::pem_file_iter(cert)
+                    .map_err(|e| pem_error(cert, e))?
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| pem_error(cert, e))?;
+                let key = PrivateKeyDer::from_pem_file(key).map_err(|e| pem_error(key, e))?;
+
+                builder.with_client_auth_cert(chain, key)?
+            },
+            (None, None) => builder.with_no_client_auth(),
+            _ => {
+                return Err(NtripClientError::Tls(
+                    "client certificate and key must be provided together".into(),
+                ))
+            },
+        };
+
+        Ok(config)
+    }
+
+    /// Builds a [TlsConnector] described by these settings
+    pub fn connector(&self) -> Result<TlsConnector, NtripClientError> {
+        Ok(TlsConnector::from(Arc::new(self.client_config()?)))
+    }
+}
+
+fn pem_error(path: &std::path::Path, e: impl std::fmt::Display) -> NtripClientError {
+    NtripClientError::Tls(format!("{}: {}", path.display(), e))
+}
+
+/// Parses a pinned key: base64 SHA-256, optionally prefixed with "sha256//"
+fn parse_pin(pin: &str) -> Result<[u8; 32], NtripClientError> {
+    let encoded = pin.trim().trim_start_matches("sha256//");
+
+    general_purpose::STANDARD
+        .decode(encoded)
+        .ok()
+        .and_then(|hash| <[u8; 32]>::try_from(hash).ok())
+        .ok_or_else(|| NtripClientError::Tls(format!("invalid pinned key \"{}\"", pin)))
+}
+
+/// Verifies the certificate chain as usual, then requires the
+/// server public key to match one of the pins.
+#[derive(Debug)]
+struct PinnedVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: Vec<[u8; 32]>,
+}
+
+impl PinnedVerifier {
+    fn new(
+        roots: RootCertStore,
+        pins: &[String],
+        provider: Arc<CryptoProvider>,
+    ) -> Result<Self, NtripClientError> {
+        let inner = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider)
+            .build()
+            .map_err(|e| NtripClientError::Tls(e.to_string()))?;
+
+        Ok(Self {
+            inner,
+            pins: pins
+                .iter()
+                .map(|p| parse_pin(p))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+
+        let cert = webpki::EndEntityCert::try_from(end_entity)
+            .map_err(|_| rustls::Error::InvalidCertificate(CertificateError::BadEncoding))?;
+        let hash: [u8; 32] = Sha256::digest(cert.subject_public_key_info().as_ref()).into();
+
+        if self.pins.contains(&hash) {
+            Ok(verified)
+        } else {
+            warn!(
+                "Server key sha256//{} is not pinned",
+                general_purpose::STANDARD.encode(hash)
+            );
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tls_settings() {
+        let settings = TlsSettings::default();
+        assert!(settings.client_config().is_ok());
+
+        // Nothing left to trust
+        assert!(settings.without_builtin_roots().client_config().is_err());
+
+        // Missing key
+        let mut incomplete = settings.clone();
+        incomplete.client_cert = Some("client.pem".into());
+        assert!(incomplete.client_config().is_err());
+
+        let pin = general_purpose::STANDARD.encode([7u8; 32]);
+        assert_eq!(parse_pin(&format!("sha256//{}", pin)).unwrap(), [7u8; 32]);
+        assert!(settings.with_pinned_key(&pin).client_config().is_ok());
+        assert!(settings
+            .with_pinned_key("c2hvcnQ=")
+            .client_config()
+            .is_err());
+
+        let name = settings
+            .with_server_name("caster.internal")
+            .server_name("10.0.0.1")
+            .unwrap();
+        assert_eq!(name.to_str(), "caster.internal");
+        assert_eq!(settings.server_name("[::1]").unwrap().to_str(), "::1");
+    }
+}